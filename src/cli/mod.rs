@@ -0,0 +1,285 @@
+use std::fmt::Display;
+
+use crate::log::LogLevel;
+
+pub const USAGE: &str = "\
+Usage: talk-that-talk [command] [options]
+
+Commands:
+  run            Intercept the configured keyboards (default)
+  check          Validate the config and exit
+  list-devices   List input devices, with config snippets to match them
+  monitor        Run, printing every event and what came of it
+  record         Run, recording key events to a file
+  replay         Feed recorded key events through the rules, printing what comes of them
+  help           Show this message
+
+Options:
+  -c, --config <path>      Config file, config.json by default
+      --log-level <level>  error, warn, info (default) or debug
+      --dry-run            Read keyboards without grabbing them, and send nothing out
+      --wait               Wait for keyboards missing at startup, instead of failing
+      --json               Print list-devices as JSON
+  -o, --output <path>      File record writes to
+  -i, --input <path>       File replay reads from
+      --speed <factor>     How much faster than recorded to replay, 1 by default,
+                           0 for no waiting
+
+Exit codes:
+  0   success
+  64  invalid command line
+  69  a keyboard can't be found or grabbed at startup, without --wait
+  73  the virtual output device can't be created
+  74  a recording can't be read or written
+  78  invalid config";
+
+/// Exit codes, from sysexits.h.
+pub mod exit {
+    pub const USAGE: u8 = 64;
+    pub const UNAVAILABLE: u8 = 69;
+    pub const CANT_CREATE: u8 = 73;
+    pub const IO_ERR: u8 = 74;
+    pub const CONFIG: u8 = 78;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Check,
+    ListDevices,
+    Monitor,
+    Record,
+    Replay,
+    Help,
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Command::Run => "run",
+            Command::Check => "check",
+            Command::ListDevices => "list-devices",
+            Command::Monitor => "monitor",
+            Command::Record => "record",
+            Command::Replay => "replay",
+            Command::Help => "help",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The parsed command line.
+#[derive(Debug, PartialEq, Getters)]
+pub struct Cli {
+    #[getset(get = "pub")]
+    command: Command,
+
+    #[getset(get = "pub")]
+    config: String,
+
+    #[getset(get = "pub")]
+    log_level: LogLevel,
+
+    #[getset(get = "pub")]
+    dry_run: bool,
+
+    #[getset(get = "pub")]
+    wait: bool,
+
+    #[getset(get = "pub")]
+    json: bool,
+
+    /// Where `record` writes to, or `replay` reads from.
+    #[getset(get = "pub")]
+    recording: String,
+
+    #[getset(get = "pub")]
+    speed: f64,
+}
+
+impl Default for Cli {
+    fn default() -> Self {
+        Self {
+            command: Command::Run,
+            config: String::from("config.json"),
+            log_level: LogLevel::Info,
+            dry_run: false,
+            wait: false,
+            json: false,
+            recording: String::new(),
+            speed: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    UnknownCommand(String),
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue(String),
+    Unexpected(String),
+    /// An option the command doesn't take.
+    NotFor(String, Command),
+    /// An option the command can't do without.
+    Needs(Command, String),
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::UnknownCommand(command) => write!(f, "Unknown command \"{command}\""),
+            CliError::UnknownOption(option) => write!(f, "Unknown option \"{option}\""),
+            CliError::MissingValue(option) => write!(f, "{option} needs a value"),
+            CliError::InvalidValue(message) => write!(f, "{message}"),
+            CliError::Unexpected(arg) => write!(f, "Unexpected argument \"{arg}\""),
+            CliError::NotFor(option, command) => write!(f, "{command} doesn't take {option}"),
+            CliError::Needs(command, option) => write!(f, "{command} needs {option} <path>"),
+        }
+    }
+}
+
+/// Parses the arguments, without the program name.
+pub fn parse(args: &[String]) -> Result<Cli, CliError> {
+    let mut cli = Cli::default();
+    let mut args = args.iter();
+    // options given, to check they make sense for the command
+    let mut given: Vec<&str> = vec![];
+
+    let mut command = None;
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| CliError::MissingValue(option.to_string()))
+        };
+        match arg.as_str() {
+            "-c" | "--config" => cli.config = value(arg)?,
+            "--log-level" => {
+                cli.log_level = value(arg)?.parse().map_err(CliError::InvalidValue)?;
+            }
+            "-o" | "--output" | "-i" | "--input" => cli.recording = value(arg)?,
+            "--speed" => {
+                cli.speed = match value(arg)?.parse() {
+                    Ok(speed) if speed >= 0.0 => speed,
+                    _ => return Err(CliError::InvalidValue("Invalid speed".to_string())),
+                };
+            }
+            "--dry-run" => cli.dry_run = true,
+            "--wait" => cli.wait = true,
+            "--json" => cli.json = true,
+            "-h" | "--help" => command = Some(Command::Help),
+            option if option.starts_with('-') => {
+                return Err(CliError::UnknownOption(option.to_string()))
+            }
+            name if command.is_none() => {
+                command = Some(match name {
+                    "run" => Command::Run,
+                    "check" => Command::Check,
+                    "list-devices" => Command::ListDevices,
+                    "monitor" => Command::Monitor,
+                    "record" => Command::Record,
+                    "replay" => Command::Replay,
+                    "help" => Command::Help,
+                    _ => return Err(CliError::UnknownCommand(name.to_string())),
+                });
+                continue;
+            }
+            extra => return Err(CliError::Unexpected(extra.to_string())),
+        }
+        given.push(arg);
+    }
+    cli.command = command.unwrap_or(Command::Run);
+
+    let takes = |option: &str| match option {
+        "-c" | "--config" => !matches!(cli.command, Command::ListDevices),
+        "--dry-run" | "--wait" => matches!(
+            cli.command,
+            Command::Run | Command::Monitor | Command::Record
+        ),
+        "--json" => cli.command == Command::ListDevices,
+        "-o" | "--output" => cli.command == Command::Record,
+        "-i" | "--input" | "--speed" => cli.command == Command::Replay,
+        _ => true,
+    };
+    if let Some(option) = given.into_iter().find(|o| !takes(o)) {
+        return Err(CliError::NotFor(option.to_string(), cli.command));
+    }
+
+    match cli.command {
+        Command::Record if cli.recording.is_empty() => {
+            return Err(CliError::Needs(cli.command, "--output".to_string()))
+        }
+        Command::Replay if cli.recording.is_empty() => {
+            return Err(CliError::Needs(cli.command, "--input".to_string()))
+        }
+        _ => (),
+    }
+
+    Ok(cli)
+}
+
+#[cfg(test)]
+mod cli_module_test {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Cli, CliError> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse(&args)
+    }
+
+    #[test]
+    fn runs_with_default_config_when_given_nothing() {
+        assert_eq!(parse_line(""), Ok(Cli::default()));
+    }
+
+    #[test]
+    fn parses_commands_and_options() {
+        let cli = parse_line("monitor --dry-run -c mine.json --log-level debug").unwrap();
+        assert_eq!(*cli.command(), Command::Monitor);
+        assert_eq!(cli.config(), "mine.json");
+        assert_eq!(*cli.log_level(), LogLevel::Debug);
+        assert!(cli.dry_run());
+        assert!(!cli.wait());
+
+        let cli = parse_line("run --wait").unwrap();
+        assert!(cli.wait());
+
+        let cli = parse_line("--json list-devices").unwrap();
+        assert_eq!(*cli.command(), Command::ListDevices);
+        assert!(cli.json());
+
+        let cli = parse_line("replay -i typing.rec --speed 10").unwrap();
+        assert_eq!(cli.recording(), "typing.rec");
+        assert!((cli.speed() - 10.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn rejects_what_it_doesnt_understand() {
+        assert_eq!(
+            parse_line("start"),
+            Err(CliError::UnknownCommand("start".to_string()))
+        );
+        assert_eq!(
+            parse_line("run --verbose"),
+            Err(CliError::UnknownOption("--verbose".to_string()))
+        );
+        assert_eq!(
+            parse_line("run --config"),
+            Err(CliError::MissingValue("--config".to_string()))
+        );
+        assert_eq!(
+            parse_line("check --dry-run"),
+            Err(CliError::NotFor("--dry-run".to_string(), Command::Check))
+        );
+        assert!(parse_line("run --log-level loud").is_err());
+        assert_eq!(
+            parse_line("record"),
+            Err(CliError::Needs(Command::Record, "--output".to_string()))
+        );
+        assert_eq!(
+            parse_line("record -o typing.rec -i other.rec"),
+            Err(CliError::NotFor("-i".to_string(), Command::Record))
+        );
+    }
+}
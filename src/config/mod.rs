@@ -0,0 +1,275 @@
+pub mod timing;
+pub mod watcher;
+
+use std::{collections::HashMap, fmt::Display, fs, io};
+
+use serde::Deserialize;
+
+use crate::{
+    event_processor::{layers::Layer, sequencer::SequencerKind},
+    rules::Ruleset,
+    stuffs::keyboard::Keyboard,
+};
+
+use self::timing::Timing;
+
+#[derive(Deserialize, Getters, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[getset(get = "pub")]
+    keyboards: Vec<Keyboard>,
+
+    #[getset(get = "pub")]
+    rules: Ruleset,
+
+    #[serde(default)]
+    #[getset(get = "pub")]
+    layers: HashMap<String, Layer>,
+
+    #[serde(default)]
+    #[getset(get = "pub")]
+    timing: Timing,
+
+    #[serde(default)]
+    #[getset(get = "pub")]
+    sequencer: SequencerKind,
+}
+
+impl Config {
+    /// Checks what the JSON structure alone can't, like device aliases used in triggers.
+    fn validate(&self) -> Result<(), String> {
+        let known_device = |alias: &str| self.keyboards.iter().any(|k| k.alias() == alias);
+
+        for rule in self.rules.rules() {
+            for alias in rule.trigger().devices() {
+                if !known_device(alias) {
+                    return Err(format!(
+                        "Unknown device \"{alias}\" in trigger \"{}\"",
+                        rule.trigger()
+                    ));
+                }
+            }
+            if let Some(layer) = rule.output().layer() {
+                if !self.layers.contains_key(layer) {
+                    return Err(format!(
+                        "Unknown layer \"{layer}\" in rule \"{}\"",
+                        rule.trigger()
+                    ));
+                }
+            }
+        }
+
+        for keyboard in &self.keyboards {
+            if keyboard.device_matcher().is_empty() {
+                return Err(format!(
+                    "Keyboard \"{}\" needs a \"path\" or a \"match\"",
+                    keyboard.alias()
+                ));
+            }
+        }
+
+        for (name, layer) in &self.layers {
+            if let Some(alias) = layer.devices().iter().find(|a| !known_device(a)) {
+                return Err(format!("Unknown device \"{alias}\" in layer \"{name}\""));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, io::Error),
+    Parse {
+        path: String,
+        source_line: String,
+        error: serde_json::Error,
+    },
+    Invalid(String, String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "Failed to read config {path}: {err}"),
+            ConfigError::Parse {
+                path,
+                source_line,
+                error,
+            } => {
+                write!(f, "Invalid config {path}: {error}")?;
+                if error.line() > 0 {
+                    write!(f, "\n{:>5} | {source_line}", error.line())?;
+                }
+                Ok(())
+            }
+            ConfigError::Invalid(path, message) => write!(f, "Invalid config {path}: {message}"),
+        }
+    }
+}
+
+/// Reads and parses the JSON config file at `path`.
+pub fn load(path: &str) -> Result<Config, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_string(), err))?;
+    parse(path, &text)
+}
+
+/// Parses a JSON config. `path` is only used to label errors.
+pub fn parse(path: &str, text: &str) -> Result<Config, ConfigError> {
+    let config: Config = serde_json::from_str(text).map_err(|error| ConfigError::Parse {
+        path: path.to_string(),
+        source_line: text
+            .lines()
+            .nth(error.line().saturating_sub(1))
+            .unwrap_or_default()
+            .to_string(),
+        error,
+    })?;
+
+    config
+        .validate()
+        .map_err(|message| ConfigError::Invalid(path.to_string(), message))?;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod config_module_test {
+    use crate::{interceptor::rule_output::Output, stuffs::key_code::KeyCode};
+
+    use super::*;
+
+    #[test]
+    fn can_parse_keyboards_and_rules() {
+        let text = r#"{
+            "keyboards": [
+                { "alias": "L1", "name": "Left Keyboard", "path": "usb/0/0/input0" }
+            ],
+            "timing": { "sequence_timeout": 250 },
+            "rules": {
+                "L1 CAPSLOCK": { "Map": "Esc" },
+                "L1 E, L1 F": { "Cmd": ["firefox", []] },
+                "L1 RIGHTALT": { "Sequence": [["LeftCtrl", 1], ["LeftCtrl", 0]] }
+            }
+        }"#;
+
+        let config = parse("test.json", text).unwrap();
+        assert_eq!(config.keyboards()[0].alias(), "L1");
+        assert_eq!(config.keyboards()[0].path(), "usb/0/0/input0");
+        assert_eq!(config.rules().len(), 3);
+        assert_eq!(*config.timing().sequence_timeout(), 250);
+        assert_eq!(config.rules().rules()[0].output(), &Output::Map(KeyCode(1)));
+        assert_eq!(*config.sequencer(), SequencerKind::Classic);
+    }
+
+    #[test]
+    fn keyboards_can_set_their_modifiers() {
+        let text = r#"{
+            "keyboards": [
+                { "alias": "L1", "name": "Left Keyboard", "path": "usb/0/0/input0" },
+                { "alias": "R1", "name": "Right Keyboard", "path": "usb/1/1/input0",
+                  "modifiers": ["Ctrl", "CapsLock"] }
+            ],
+            "rules": {}
+        }"#;
+
+        let config = parse("test.json", text).unwrap();
+        let (left, right) = (&config.keyboards()[0], &config.keyboards()[1]);
+        assert!(left.modifiers().contains(KeyCode::from("RightAlt")));
+        assert!(!left.modifiers().contains(KeyCode::from("CapsLock")));
+        assert!(right.modifiers().contains(KeyCode::from("CapsLock")));
+        assert!(right.modifiers().contains(KeyCode::from("RightCtrl")));
+        assert!(!right.modifiers().contains(KeyCode::from("LeftShift")));
+    }
+
+    #[test]
+    fn example_config_is_valid() {
+        let text = include_str!("../../config.example.json");
+        assert!(parse("config.example.json", text).is_ok());
+    }
+
+    #[test]
+    fn parse_error_points_at_offending_line() {
+        let text = "{\n  \"keyboards\": [],\n  \"rules\": {\n    \"L1 CAPSLOCK\": { \"Mapp\": \"Esc\" }\n  }\n}";
+
+        let err = parse("test.json", text).unwrap_err();
+        let ConfigError::Parse { error, .. } = &err else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(error.line(), 4);
+        assert!(err
+            .to_string()
+            .contains("    4 |     \"L1 CAPSLOCK\": { \"Mapp\": \"Esc\" }"));
+    }
+
+    #[test]
+    fn unknown_devices_in_triggers_are_rejected() {
+        let text = r#"{ "keyboards": [], "rules": { "L1 A, R1 B": { "Map": "C" } } }"#;
+
+        let err = parse("test.json", text).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config test.json: Unknown device \"L1\" in trigger \"L1 A, R1 B\""
+        );
+    }
+
+    #[test]
+    fn keyboards_need_a_device_to_match() {
+        let text = r#"{ "keyboards": [{ "alias": "L1", "name": "Left Keyboard" }], "rules": {} }"#;
+
+        let err = parse("test.json", text).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config test.json: Keyboard \"L1\" needs a \"path\" or a \"match\""
+        );
+    }
+
+    #[test]
+    fn unknown_keyboard_fields_are_rejected() {
+        let text = "{\n  \"keyboards\": [\n    { \"alias\": \"L1\", \"name\": \"Left Keyboard\", \"path\": \"usb/0/0/input0\",\n      \"passtrough\": [\"Esc\"] }\n  ],\n  \"rules\": {}\n}";
+
+        let err = parse("test.json", text).unwrap_err();
+        let ConfigError::Parse { error, .. } = &err else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(error.line(), 4);
+        assert!(err.to_string().contains("unknown field `passtrough`"));
+    }
+
+    #[test]
+    fn layers_must_exist_and_use_known_devices() {
+        let text = r#"{
+            "keyboards": [{ "alias": "L1", "name": "Left Keyboard", "path": "usb/0/0/input0" }],
+            "layers": { "nav": { "devices": ["L1"], "keys": { "H": "Left" } } },
+            "rules": { "L1 TAB": { "MomentaryLayer": "nav" }, "L1 A, L1 B": { "ToggleLayer": "num" } }
+        }"#;
+        let err = parse("test.json", text).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config test.json: Unknown layer \"num\" in rule \"L1 A, L1 B\""
+        );
+
+        let text = r#"{
+            "keyboards": [],
+            "layers": { "nav": { "devices": ["R1"], "keys": { "H": "Left" } } },
+            "rules": {}
+        }"#;
+        let err = parse("test.json", text).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config test.json: Unknown device \"R1\" in layer \"nav\""
+        );
+    }
+
+    #[test]
+    fn invalid_key_names_are_rejected_at_load() {
+        let text = "{\n  \"keyboards\": [],\n  \"rules\": {\n    \"L1 A\": { \"Map\": \"LeftCrtl\" }\n  }\n}";
+
+        let err = parse("test.json", text).unwrap_err();
+        let ConfigError::Parse { error, .. } = &err else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(error.line(), 4);
+        assert!(err.to_string().contains("Invalid key name \"LeftCrtl\""));
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Timing thresholds of the sequence engine, in milliseconds.
+#[derive(Deserialize, Getters, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timing {
+    /// How long a sequence that triggers a rule but also starts a longer one
+    /// waits for the next key before the shorter rule fires.
+    #[getset(get = "pub")]
+    sequence_timeout: u64,
+
+    /// Keys pressed within this long of the first key of a step form a chord.
+    #[getset(get = "pub")]
+    chord_window: u64,
+
+    /// A key released out of order only counts as part of the sequence
+    /// when it was held longer than this.
+    #[getset(get = "pub")]
+    orphan_window: u64,
+
+    /// How remapped keys repeat while held.
+    #[getset(get = "pub")]
+    repeat: Repeat,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            sequence_timeout: 300,
+            chord_window: 50,
+            orphan_window: 150,
+            repeat: Repeat::Forward,
+        }
+    }
+}
+
+/// Key repeat for remapped outputs.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// Held outputs never repeat.
+    Off,
+    /// Held outputs repeat along with the kernel repeat events of the held key.
+    Forward,
+    /// Held outputs repeat after `delay` ms, `rate` times per second.
+    Synthesize { delay: u64, rate: u64 },
+}
+
+/// Overrides of the `Timing` windows for a single keyboard or rule, in milliseconds.
+#[derive(Deserialize, Getters, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(default, deny_unknown_fields)]
+pub struct Windows {
+    #[getset(get = "pub")]
+    chord_window: Option<u64>,
+
+    #[getset(get = "pub")]
+    orphan_window: Option<u64>,
+}
+
+impl Windows {
+    pub fn new(chord_window: Option<u64>, orphan_window: Option<u64>) -> Self {
+        Self {
+            chord_window,
+            orphan_window,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chord_window.is_none() && self.orphan_window.is_none()
+    }
+}
+
+impl Timing {
+    pub fn sequence_timeout_duration(&self) -> Duration {
+        Duration::from_millis(self.sequence_timeout)
+    }
+}
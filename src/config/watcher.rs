@@ -0,0 +1,68 @@
+use std::{fs, sync::mpsc::Sender, thread, time::Duration};
+
+use crate::interceptor::TransmitSignal;
+
+use super::load;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches the config file at `path` and sends a `TransmitSignal::Reload`
+/// every time its content changes and still parses.
+/// An invalid file is reported and skipped, so the running ruleset stays active.
+pub fn watch(path: &str, tx: Sender<TransmitSignal>) {
+    watch_with_interval(path, tx, POLL_INTERVAL);
+}
+
+fn watch_with_interval(path: &str, tx: Sender<TransmitSignal>, interval: Duration) {
+    let path = path.to_string();
+    let mut last_content = fs::read_to_string(&path).ok();
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        let content = fs::read_to_string(&path).ok();
+        if content.is_none() || content == last_content {
+            continue;
+        }
+        last_content = content;
+
+        match load(&path) {
+            Ok(config) => {
+                if tx.send(TransmitSignal::Reload(config)).is_err() {
+                    break;
+                }
+            }
+            Err(err) => error!("Config reload failed, keeping current ruleset.\n{err}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod watcher_module_test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn sends_reload_only_for_valid_changes() {
+        let path = std::env::temp_dir().join(format!("ttt-watcher-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, r#"{ "keyboards": [], "rules": {} }"#).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        watch_with_interval(path, tx, Duration::from_millis(10));
+
+        fs::write(path, r#"{ "keyboards": [], "rules": { "#).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        fs::write(
+            path,
+            r#"{ "keyboards": [], "rules": { "A": { "Map": "B" } } }"#,
+        )
+        .unwrap();
+        let signal = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(signal, TransmitSignal::Reload(config) if config.rules().len() == 1));
+
+        fs::remove_file(path).ok();
+    }
+}
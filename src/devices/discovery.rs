@@ -0,0 +1,234 @@
+use std::{fmt::Display, path::Path};
+
+use evdev::{AbsoluteAxisType, Device, Key, RelativeAxisType};
+use serde::Serialize;
+
+use super::matcher::DeviceInfo;
+
+/// What kind of input a device produces.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    Keys,
+    Buttons,
+    Pointer,
+    Absolute,
+}
+
+impl Capability {
+    pub fn of(device: &Device) -> Vec<Capability> {
+        let keys = device.supported_keys();
+        let has_key = |key| matches!(keys, Some(k) if k.contains(key));
+
+        let mut capabilities = vec![];
+        if has_key(Key::KEY_A) || has_key(Key::KEY_ENTER) {
+            capabilities.push(Capability::Keys);
+        }
+        if has_key(Key::BTN_LEFT) {
+            capabilities.push(Capability::Buttons);
+        }
+        if matches!(device.supported_relative_axes(), Some(r) if r.contains(RelativeAxisType::REL_X))
+        {
+            capabilities.push(Capability::Pointer);
+        }
+        if matches!(device.supported_absolute_axes(), Some(a) if a.contains(AbsoluteAxisType::ABS_X))
+        {
+            capabilities.push(Capability::Absolute);
+        }
+        capabilities
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Capability::Keys => "keys",
+            Capability::Buttons => "buttons",
+            Capability::Pointer => "pointer",
+            Capability::Absolute => "absolute",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// One device as `list-devices` shows it.
+#[derive(Serialize, Debug)]
+pub struct Listing {
+    node: String,
+    name: String,
+    path: String,
+    uniq: String,
+    bus: String,
+    vendor: String,
+    product: String,
+    capabilities: Vec<Capability>,
+    /// A `Keyboard` entry for the config that picks this device.
+    #[serde(serialize_with = "as_json")]
+    config: String,
+}
+
+fn as_json<S: serde::Serializer>(config: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let value: serde_json::Value =
+        serde_json::from_str(config).map_err(serde::ser::Error::custom)?;
+    value.serialize(serializer)
+}
+
+impl Listing {
+    pub fn new(node: &Path, info: &DeviceInfo, capabilities: Vec<Capability>, alias: &str) -> Self {
+        Self {
+            node: node.display().to_string(),
+            name: info.name.clone(),
+            path: info.path.clone(),
+            uniq: info.uniq.clone(),
+            bus: info.bus.clone(),
+            vendor: format!("{:04x}", info.vendor),
+            product: format!("{:04x}", info.product),
+            capabilities,
+            config: snippet(alias, info),
+        }
+    }
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let capabilities: Vec<String> = self
+            .capabilities
+            .iter()
+            .map(Capability::to_string)
+            .collect();
+
+        writeln!(f, "{}", self.node)?;
+        writeln!(f, "    name:         {}", self.name)?;
+        writeln!(f, "    path:         {}", self.path)?;
+        writeln!(
+            f,
+            "    id:           {} {}:{}",
+            self.bus, self.vendor, self.product
+        )?;
+        if !self.uniq.is_empty() {
+            writeln!(f, "    uniq:         {}", self.uniq)?;
+        }
+        writeln!(f, "    capabilities: {}", capabilities.join(", "))?;
+        write!(f, "    config:       {}", self.config)
+    }
+}
+
+/// A `Keyboard` entry matching the device by what stays the same across USB ports:
+/// its name, IDs and uniq, plus the interface at the end of its path.
+fn snippet(alias: &str, info: &DeviceInfo) -> String {
+    let quote = |text: &str| serde_json::to_string(text).unwrap();
+
+    let mut fields = vec![format!("\"name\": {}", quote(&info.name))];
+    if info.vendor != 0 || info.product != 0 {
+        fields.push(format!("\"vendor\": \"{:04x}\"", info.vendor));
+        fields.push(format!("\"product\": \"{:04x}\"", info.product));
+    }
+    if !info.uniq.is_empty() {
+        fields.push(format!("\"uniq\": {}", quote(&info.uniq)));
+    }
+    if let Some((_, interface)) = info.path.rsplit_once('/') {
+        fields.push(format!("\"path\": {}", quote(&format!("*/{interface}"))));
+    }
+
+    format!(
+        "{{ \"alias\": {}, \"name\": {}, \"match\": {{ {} }} }}",
+        quote(alias),
+        quote(&info.name),
+        fields.join(", ")
+    )
+}
+
+/// Every evdev device, in device node order.
+pub fn list() -> Vec<Listing> {
+    let mut devices: Vec<_> = evdev::enumerate().collect();
+    devices.sort_by_key(|(node, _)| (node.as_os_str().len(), node.clone()));
+
+    devices
+        .iter()
+        .enumerate()
+        .map(|(i, (node, device))| {
+            let alias = format!("K{}", i + 1);
+            Listing::new(
+                node,
+                &DeviceInfo::from(device),
+                Capability::of(device),
+                &alias,
+            )
+        })
+        .collect()
+}
+
+/// Prints every device, as text or as a JSON array.
+pub fn print(json: bool) {
+    let listings = list();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&listings).unwrap());
+        return;
+    }
+
+    if listings.is_empty() {
+        println!("No input devices found, reading them may need root or the input group.");
+    }
+    for listing in &listings {
+        println!("{listing}\n");
+    }
+}
+
+#[cfg(test)]
+mod discovery_module_test {
+    use crate::stuffs::keyboard::Keyboard;
+
+    use super::*;
+
+    fn mock_listing() -> (DeviceInfo, Listing) {
+        let info = DeviceInfo {
+            name: "Kinesis Advantage2 Keyboard".to_string(),
+            path: "usb-0000:00:14.0-3/input0".to_string(),
+            uniq: String::new(),
+            bus: "USB".to_string(),
+            vendor: 0x29ea,
+            product: 0x0102,
+        };
+        let listing = Listing::new(
+            Path::new("/dev/input/event3"),
+            &info,
+            vec![Capability::Keys],
+            "K1",
+        );
+        (info, listing)
+    }
+
+    #[test]
+    fn config_snippet_picks_the_device() {
+        let (info, listing) = mock_listing();
+        assert_eq!(
+            listing.config,
+            r#"{ "alias": "K1", "name": "Kinesis Advantage2 Keyboard", "match": { "name": "Kinesis Advantage2 Keyboard", "vendor": "29ea", "product": "0102", "path": "*/input0" } }"#
+        );
+
+        let keyboard: Keyboard = serde_json::from_str(&listing.config).unwrap();
+        assert!(keyboard.device_matcher().matches(&info));
+    }
+
+    #[test]
+    fn prints_as_text() {
+        let (_, listing) = mock_listing();
+        let text = listing.to_string();
+        assert!(
+            text.starts_with("/dev/input/event3\n    name:         Kinesis Advantage2 Keyboard\n")
+        );
+        assert!(text.contains("    id:           USB 29ea:0102\n"));
+        assert!(text.contains("    capabilities: keys\n"));
+        assert!(!text.contains("uniq"));
+    }
+
+    #[test]
+    fn prints_as_json() {
+        let (_, listing) = mock_listing();
+        let value = serde_json::to_value(&listing).unwrap();
+        assert_eq!(value["node"], "/dev/input/event3");
+        assert_eq!(value["vendor"], "29ea");
+        assert_eq!(value["capabilities"][0], "keys");
+        assert_eq!(value["config"]["match"]["path"], "*/input0");
+    }
+}
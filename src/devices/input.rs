@@ -1,5 +1,9 @@
+use std::{fmt::Display, io, path::PathBuf};
+
 use evdev::{Device, InputEvent, InputEventKind};
 
+use super::matcher::{DeviceInfo, DeviceMatcher};
+
 pub trait EventKindCheck {
     fn is_type_key(&self) -> bool;
 }
@@ -10,25 +14,121 @@ impl EventKindCheck for InputEvent {
     }
 }
 
-pub fn from_path(path: &str) -> Device {
-    let devices = evdev::enumerate().map(|t| t.1).collect::<Vec<_>>();
-    let mut device_index = 0;
-    for (i, d) in devices.iter().enumerate() {
-        if d.physical_path().is_some() && d.physical_path().unwrap() == path {
-            device_index = i;
-            break;
+#[derive(Debug)]
+pub enum DeviceError {
+    /// What was looked for, and every device there is.
+    NotFound(String, Vec<DeviceInfo>),
+    /// What was looked for, and the devices that matched.
+    Ambiguous(String, Vec<DeviceInfo>),
+    /// The device node matched, and why it can't be grabbed, like another program holding it.
+    Grab(PathBuf, io::Error),
+}
+
+impl Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (message, devices) = match self {
+            DeviceError::NotFound(matcher, devices) => {
+                (format!("No input device matches {matcher}"), devices)
+            }
+            DeviceError::Ambiguous(matcher, devices) => (
+                format!("Several input devices match {matcher}, narrow it down with \"path\" or \"uniq\""),
+                devices,
+            ),
+            DeviceError::Grab(node, err) => {
+                return write!(f, "Failed to grab {}: {err}", node.display());
+            }
+        };
+        write!(f, "{message}")?;
+        for device in devices {
+            write!(f, "\n    {device}")?;
         }
+        Ok(())
     }
-    devices.into_iter().nth(device_index).unwrap()
 }
 
-#[allow(dead_code)]
-pub fn print_paths() {
-    let devices = evdev::enumerate().map(|t| t.1).collect::<Vec<_>>();
-    for d in &devices {
-        if let Some(path) = d.physical_path() {
-            let name = d.name().unwrap();
-            println!("{name} = {path}");
-        }
+/// Finds the one device `matcher` picks among `devices`, listed with their device node,
+/// and takes it out. When none or several match, the error lists the candidates:
+/// every device, or the ones that matched.
+pub fn find_in(
+    devices: &mut Vec<(PathBuf, Device)>,
+    matcher: &DeviceMatcher,
+) -> Result<(PathBuf, Device), DeviceError> {
+    let infos: Vec<DeviceInfo> = devices.iter().map(|(_, d)| DeviceInfo::from(d)).collect();
+    pick(&infos, matcher).map(|i| devices.swap_remove(i))
+}
+
+fn pick(devices: &[DeviceInfo], matcher: &DeviceMatcher) -> Result<usize, DeviceError> {
+    let matching: Vec<usize> = (0..devices.len())
+        .filter(|i| matcher.matches(&devices[*i]))
+        .collect();
+
+    match matching[..] {
+        [i] => Ok(i),
+        [] => Err(DeviceError::NotFound(matcher.to_string(), devices.to_vec())),
+        _ => Err(DeviceError::Ambiguous(
+            matcher.to_string(),
+            matching.iter().map(|i| devices[*i].clone()).collect(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod input_module_test {
+    use super::*;
+
+    fn mock_devices() -> Vec<DeviceInfo> {
+        let device = |name: &str, path: &str| DeviceInfo {
+            name: name.to_string(),
+            path: path.to_string(),
+            bus: "USB".to_string(),
+            ..Default::default()
+        };
+        vec![
+            device("Left Keyboard", "usb-1/input0"),
+            device("Left Keyboard", "usb-1/input1"),
+            device("Right Keyboard", "usb-2/input0"),
+        ]
+    }
+
+    fn matcher(json: &str) -> DeviceMatcher {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn picks_the_only_match() {
+        let devices = mock_devices();
+        assert_eq!(
+            pick(&devices, &matcher(r#"{ "name": "Right*" }"#)).unwrap(),
+            2
+        );
+        assert_eq!(
+            pick(
+                &devices,
+                &matcher(r#"{ "name": "Left*", "path": "*/input1" }"#)
+            )
+            .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn no_match_lists_every_device() {
+        let err = pick(&mock_devices(), &matcher(r#"{ "name": "Kinesis*" }"#)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No input device matches name \"Kinesis*\"\n    \
+             \"Left Keyboard\" USB 0000:0000 usb-1/input0\n    \
+             \"Left Keyboard\" USB 0000:0000 usb-1/input1\n    \
+             \"Right Keyboard\" USB 0000:0000 usb-2/input0"
+        );
+    }
+
+    #[test]
+    fn several_matches_list_the_matches() {
+        let err = pick(&mock_devices(), &matcher(r#"{ "name": "Left*" }"#)).unwrap_err();
+        assert!(matches!(&err, DeviceError::Ambiguous(_, found) if found.len() == 2));
+        assert!(err
+            .to_string()
+            .starts_with("Several input devices match name \"Left*\""));
     }
 }
@@ -0,0 +1,234 @@
+use std::fmt::Display;
+
+use evdev::Device;
+use serde::Deserialize;
+
+/// What we know about an input device, as far as matching goes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub path: String,
+    pub uniq: String,
+    pub bus: String,
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl From<&Device> for DeviceInfo {
+    fn from(device: &Device) -> Self {
+        let id = device.input_id();
+        Self {
+            name: device.name().unwrap_or_default().to_string(),
+            path: device.physical_path().unwrap_or_default().to_string(),
+            uniq: device.unique_name().unwrap_or_default().to_string(),
+            bus: id.bus_type().to_string(),
+            vendor: id.vendor(),
+            product: id.product(),
+        }
+    }
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\"{}\" {} {:04x}:{:04x} {}",
+            self.name, self.bus, self.vendor, self.product, self.path
+        )
+    }
+}
+
+/// A USB style vendor or product ID, written in hex like `"046d"` or `"0x046d"`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String")]
+pub struct UsbId(pub u16);
+
+impl TryFrom<String> for UsbId {
+    type Error = String;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        let digits = input.trim_start_matches("0x");
+        u16::from_str_radix(digits, 16)
+            .map(UsbId)
+            .map_err(|_| format!("Invalid hex ID \"{input}\""))
+    }
+}
+
+/// Picks the input device of a `Keyboard`. Every field that is set has to match.
+/// `name`, `uniq` and `path` take globs, where `*` matches any text and `?` one character.
+/// `bus` is compared without case, like `"usb"` or `"bluetooth"`.
+#[derive(Deserialize, Getters, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceMatcher {
+    #[getset(get = "pub")]
+    name: Option<String>,
+
+    #[getset(get = "pub")]
+    vendor: Option<UsbId>,
+
+    #[getset(get = "pub")]
+    product: Option<UsbId>,
+
+    #[getset(get = "pub")]
+    bus: Option<String>,
+
+    #[getset(get = "pub")]
+    uniq: Option<String>,
+
+    #[getset(get = "pub")]
+    path: Option<String>,
+}
+
+impl DeviceMatcher {
+    /// Returns `true` when nothing is set, which no device should be picked by.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn set_path_if_unset(&mut self, path: &str) {
+        if self.path.is_none() && !path.is_empty() {
+            self.path = Some(path.to_string());
+        }
+    }
+
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        let text =
+            |pattern: &Option<String>, value: &str| !matches!(pattern, Some(p) if !glob(p, value));
+        let id = |expected: Option<UsbId>, value: u16| !matches!(expected, Some(e) if e.0 != value);
+
+        !self.is_empty()
+            && text(&self.name, &device.name)
+            && text(&self.uniq, &device.uniq)
+            && text(&self.path, &device.path)
+            && id(self.vendor, device.vendor)
+            && id(self.product, device.product)
+            && !matches!(&self.bus, Some(b) if !b.eq_ignore_ascii_case(&device.bus))
+    }
+}
+
+impl Display for DeviceMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(name) = &self.name {
+            parts.push(format!("name \"{name}\""));
+        }
+        if let Some(vendor) = self.vendor {
+            parts.push(format!("vendor {:04x}", vendor.0));
+        }
+        if let Some(product) = self.product {
+            parts.push(format!("product {:04x}", product.0));
+        }
+        if let Some(bus) = &self.bus {
+            parts.push(format!("bus {bus}"));
+        }
+        if let Some(uniq) = &self.uniq {
+            parts.push(format!("uniq \"{uniq}\""));
+        }
+        if let Some(path) = &self.path {
+            parts.push(format!("path \"{path}\""));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any text and `?` one character.
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was, and the text position it currently covers up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod matcher_module_test {
+    use super::*;
+
+    fn mock_device() -> DeviceInfo {
+        DeviceInfo {
+            name: "Kinesis Advantage2 Keyboard".to_string(),
+            path: "usb-0000:00:14.0-3/input0".to_string(),
+            uniq: String::new(),
+            bus: "USB".to_string(),
+            vendor: 0x29ea,
+            product: 0x0102,
+        }
+    }
+
+    fn matcher(json: &str) -> DeviceMatcher {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("Kinesis*", "Kinesis Advantage2 Keyboard"));
+        assert!(glob("*Keyboard", "Kinesis Advantage2 Keyboard"));
+        assert!(glob("usb-*-3/input?", "usb-0000:00:14.0-3/input0"));
+        assert!(glob("a*b*c", "axxbyybc"));
+        assert!(glob("*", ""));
+        assert!(!glob("Kinesis", "Kinesis Advantage2 Keyboard"));
+        assert!(!glob("usb-*-4/input?", "usb-0000:00:14.0-3/input0"));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn every_field_set_has_to_match() {
+        let device = mock_device();
+
+        assert!(matcher(r#"{ "name": "Kinesis*" }"#).matches(&device));
+        assert!(
+            matcher(r#"{ "vendor": "29ea", "product": "0x0102", "bus": "usb" }"#).matches(&device)
+        );
+        assert!(matcher(r#"{ "name": "Kinesis*", "path": "*/input0" }"#).matches(&device));
+
+        assert!(!matcher(r#"{ "name": "Kinesis*", "product": "0103" }"#).matches(&device));
+        assert!(!matcher(r#"{ "bus": "bluetooth" }"#).matches(&device));
+        assert!(!matcher(r#"{ "uniq": "?*" }"#).matches(&device));
+        assert!(!DeviceMatcher::default().matches(&device));
+    }
+
+    #[test]
+    fn invalid_ids_and_fields_are_rejected() {
+        let err = serde_json::from_str::<DeviceMatcher>(r#"{ "vendor": "xyz" }"#).unwrap_err();
+        assert!(err.to_string().contains("Invalid hex ID \"xyz\""));
+
+        let err = serde_json::from_str::<DeviceMatcher>(r#"{ "serial": "1" }"#).unwrap_err();
+        assert!(err.to_string().contains("unknown field `serial`"));
+    }
+
+    #[test]
+    fn describes_itself() {
+        let m = matcher(r#"{ "name": "Kinesis*", "vendor": "29ea", "path": "*/input0" }"#);
+        assert_eq!(
+            m.to_string(),
+            "name \"Kinesis*\", vendor 29ea, path \"*/input0\""
+        );
+        assert_eq!(
+            mock_device().to_string(),
+            "\"Kinesis Advantage2 Keyboard\" USB 29ea:0102 usb-0000:00:14.0-3/input0"
+        );
+    }
+}
@@ -1,2 +1,4 @@
+pub mod discovery;
 pub mod input;
+pub mod matcher;
 pub mod output;
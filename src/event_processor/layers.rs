@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    rules::trigger::KeyPattern,
+    stuffs::{
+        key_code::KeyCode, key_identifier::KeyIdentifier, key_state::KeyState,
+        keyboard_event::KeyboardEvent,
+    },
+};
+
+/// A named set of key remaps, applied on top of the keyboards while active.
+#[derive(Deserialize, Getters, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    /// Aliases of the keyboards this layer applies to. Empty means every keyboard.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    devices: Vec<String>,
+
+    #[getset(get = "pub")]
+    keys: HashMap<KeyCode, KeyCode>,
+}
+
+impl Layer {
+    fn applies_to(&self, alias: &str) -> bool {
+        self.devices.is_empty() || self.devices.iter().any(|d| d == alias)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    /// Active while a key is held.
+    Momentary,
+    /// Active until toggled off again.
+    Toggled,
+    /// Active for the next key press only.
+    OneShot,
+}
+
+/// What the `LayerStack` does with an event.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Layered {
+    /// Not touched by any layer, process as usual.
+    Pass,
+    /// Used to switch layers, don't process further.
+    Swallow,
+    /// Emit this key instead, with the event's value.
+    Remap(KeyCode),
+}
+
+#[derive(Debug)]
+enum Held {
+    Remap(KeyCode),
+    Momentary(String),
+}
+
+/// Active layers, the most recently activated on top.
+/// Keys pressed through a layer remember their remap until released,
+/// so switching layers mid-press never leaves a key stuck down.
+#[derive(Default, Debug)]
+pub struct LayerStack<'a> {
+    layers: HashMap<String, Layer>,
+    momentary_keys: Vec<(KeyPattern, String)>,
+    active: Vec<(String, Activation)>,
+    held: Vec<(KeyIdentifier<'a>, Held)>,
+}
+
+impl<'a> LayerStack<'a> {
+    pub fn new(layers: HashMap<String, Layer>, momentary_keys: Vec<(KeyPattern, String)>) -> Self {
+        Self {
+            layers,
+            momentary_keys,
+            ..Default::default()
+        }
+    }
+
+    /// Replaces the layer definitions, dropping activations of layers that no longer exist.
+    pub fn set_layers(
+        &mut self,
+        layers: HashMap<String, Layer>,
+        momentary_keys: Vec<(KeyPattern, String)>,
+    ) {
+        self.active.retain(|(name, _)| layers.contains_key(name));
+        self.layers = layers;
+        self.momentary_keys = momentary_keys;
+    }
+
+    pub fn active(&self) -> &[(String, Activation)] {
+        &self.active
+    }
+
+    /// Returns `true` when no key is held through a layer.
+    pub fn is_idle(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    pub fn activate(&mut self, name: &str, activation: Activation) {
+        if !self.active.contains(&(name.to_string(), activation))
+            || activation == Activation::Momentary
+        {
+            self.active.push((name.to_string(), activation));
+        }
+    }
+
+    /// Removes the most recent momentary activation of `name`.
+    pub fn deactivate(&mut self, name: &str) {
+        let position = self
+            .active
+            .iter()
+            .rposition(|(n, a)| n == name && *a == Activation::Momentary);
+        if let Some(i) = position {
+            self.active.remove(i);
+        }
+    }
+
+    pub fn toggle(&mut self, name: &str) {
+        let entry = (name.to_string(), Activation::Toggled);
+        match self.active.iter().position(|a| *a == entry) {
+            Some(i) => {
+                self.active.remove(i);
+            }
+            None => self.active.push(entry),
+        }
+    }
+
+    pub fn receive(&mut self, event: &KeyboardEvent<'a>) -> Layered {
+        if let Some(i) = self.held.iter().position(|(key, _)| key == event.key()) {
+            if *event.value() != KeyState::Up {
+                return match &self.held[i].1 {
+                    Held::Remap(code) => Layered::Remap(*code),
+                    Held::Momentary(_) => Layered::Swallow,
+                };
+            }
+            return match self.held.remove(i).1 {
+                Held::Remap(code) => Layered::Remap(code),
+                Held::Momentary(name) => {
+                    self.deactivate(&name);
+                    Layered::Swallow
+                }
+            };
+        }
+
+        if *event.value() != KeyState::Down {
+            return Layered::Pass;
+        }
+
+        let pattern = KeyPattern::from(event);
+        if let Some((_, name)) = self
+            .momentary_keys
+            .iter()
+            .find(|(k, _)| k.matches(&pattern))
+        {
+            let name = name.clone();
+            self.activate(&name, Activation::Momentary);
+            self.held.push((event.key().clone(), Held::Momentary(name)));
+            return Layered::Swallow;
+        }
+
+        let alias = event.key().device().alias();
+        let code = self.remap(alias, *event.key().code());
+        self.consume_one_shots(alias);
+
+        match code {
+            Some(code) => {
+                self.held.push((event.key().clone(), Held::Remap(code)));
+                Layered::Remap(code)
+            }
+            None => Layered::Pass,
+        }
+    }
+
+    /// Looks the key up from the top of the stack down.
+    fn remap(&self, alias: &str, code: KeyCode) -> Option<KeyCode> {
+        self.active
+            .iter()
+            .rev()
+            .filter_map(|(name, _)| self.layers.get(name))
+            .filter(|layer| layer.applies_to(alias))
+            .find_map(|layer| layer.keys.get(&code).copied())
+    }
+
+    fn consume_one_shots(&mut self, alias: &str) {
+        let layers = &self.layers;
+        self.active.retain(|(name, activation)| {
+            *activation != Activation::OneShot
+                || !matches!(layers.get(name), Some(l) if l.applies_to(alias))
+        });
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod layers_module_test {
+    use crate::{stuffs::keyboard::Keyboard, tke};
+
+    use super::*;
+
+    fn mock_keyboards() -> (Keyboard, Keyboard) {
+        let L1 = Keyboard::new("L1", "My Left Keyboard", "usb/0/0/input0");
+        let R1 = Keyboard::new("R1", "My Right Keyboard", "usb/1/1/input0");
+        (L1, R1)
+    }
+
+    fn stack<'a>() -> LayerStack<'a> {
+        let layers = serde_json::from_str(
+            r#"{
+                "nav": { "devices": ["R1"], "keys": { "H": "Left", "J": "Down" } },
+                "num": { "keys": { "J": "1" } }
+            }"#,
+        )
+        .unwrap();
+        LayerStack::new(
+            layers,
+            vec![(KeyPattern::new(Some("L1"), "CAPSLOCK"), "nav".into())],
+        )
+    }
+
+    fn remap(key: &str) -> Layered {
+        Layered::Remap(KeyCode::from(key))
+    }
+
+    #[test]
+    fn momentary_layer_is_active_while_held() {
+        let (L1, R1) = mock_keyboards();
+        let mut s = stack();
+
+        assert_eq!(s.receive(&tke!(R1 H Down 0)), Layered::Pass);
+        assert_eq!(s.receive(&tke!(R1 H Up 10)), Layered::Pass);
+
+        assert_eq!(s.receive(&tke!(L1 CAPSLOCK Down 20)), Layered::Swallow);
+        assert_eq!(s.receive(&tke!(R1 H Down 30)), remap("Left"));
+        assert_eq!(s.receive(&tke!(R1 H Hold 60)), remap("Left"));
+        assert_eq!(s.receive(&tke!(R1 H Up 90)), remap("Left"));
+        assert_eq!(s.receive(&tke!(L1 CAPSLOCK Up 100)), Layered::Swallow);
+
+        assert_eq!(s.receive(&tke!(R1 H Down 110)), Layered::Pass);
+        assert!(s.active().is_empty());
+    }
+
+    #[test]
+    fn layers_only_apply_to_their_devices() {
+        let (L1, R1) = mock_keyboards();
+        let mut s = stack();
+
+        s.toggle("nav");
+        assert_eq!(s.receive(&tke!(L1 H Down 0)), Layered::Pass);
+        assert_eq!(s.receive(&tke!(R1 H Down 10)), remap("Left"));
+    }
+
+    #[test]
+    fn keys_released_after_the_layer_is_gone_are_not_stuck() {
+        let (L1, R1) = mock_keyboards();
+        let mut s = stack();
+
+        s.receive(&tke!(L1 CAPSLOCK Down 0));
+        s.receive(&tke!(R1 J Down 10));
+        s.receive(&tke!(L1 CAPSLOCK Up 20));
+        assert_eq!(s.receive(&tke!(R1 J Up 30)), remap("Down"));
+        assert!(s.is_idle());
+    }
+
+    #[test]
+    fn toggled_layers_stack_and_the_top_one_wins() {
+        let (_, R1) = mock_keyboards();
+        let mut s = stack();
+
+        s.toggle("nav");
+        s.toggle("num");
+        assert_eq!(s.receive(&tke!(R1 J Down 0)), remap("1"));
+        assert_eq!(s.receive(&tke!(R1 H Down 10)), remap("Left"));
+
+        s.toggle("num");
+        s.receive(&tke!(R1 J Up 20));
+        assert_eq!(s.receive(&tke!(R1 J Down 30)), remap("Down"));
+
+        s.toggle("nav");
+        assert!(s.active().is_empty());
+    }
+
+    #[test]
+    fn one_shot_layer_applies_to_the_next_press_only() {
+        let (L1, R1) = mock_keyboards();
+        let mut s = stack();
+
+        s.activate("nav", Activation::OneShot);
+        assert_eq!(s.receive(&tke!(L1 A Down 0)), Layered::Pass);
+        assert_eq!(s.receive(&tke!(R1 J Down 10)), remap("Down"));
+        assert_eq!(s.receive(&tke!(R1 J Up 20)), remap("Down"));
+        assert_eq!(s.receive(&tke!(R1 J Down 30)), Layered::Pass);
+    }
+}
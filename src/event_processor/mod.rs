@@ -1,2 +1,7 @@
+pub mod layers;
+pub mod repeat;
+pub mod rollover;
 pub mod sequence_manager;
 mod neo_sequence_manager;
+pub mod sequencer;
+pub mod tap_hold;
@@ -1,35 +1,35 @@
-use std::fmt::Display;
+use crate::{
+    rules::trigger::Trigger,
+    stuffs::{key_identifier::KeyIdentifier, key_state::KeyState, keyboard_event::KeyboardEvent},
+};
 
-use crate::stuffs::{key_state::KeyState, keyboard_event::KeyboardEvent};
+use super::{sequence_manager::SequenceTiming, sequencer::Sequencer};
 
-#[derive(Default)]
-struct Sequence<'a> {
-    elements: Vec<KeyboardEvent<'a>>,
-}
-
-impl<'a> Sequence<'a> {
-    fn add(&mut self, event: KeyboardEvent<'a>) {
-        self.elements.push(event);
-    }
-}
-
-impl<'a> Display for Sequence<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.elements
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<String>>()
-                .join(", ")
-        )
-    }
+/// Where the `NeoSequenceManager` stands between events.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// No keys are held.
+    #[default]
+    Idle,
+    /// The last event added a key to the sequence.
+    Pressing,
+    /// The last event released a key, while others are still held.
+    Releasing,
 }
 
+/// Rewrite of `SequenceManager` as an explicit state machine.
+/// Events that don't fit the current state, like the release of a key
+/// that was already down before we started, are ignored instead of panicking.
 #[derive(Default)]
 pub struct NeoSequenceManager<'a> {
-    sequence: Sequence<'a>,
+    state: State,
+    sequence: Vec<KeyboardEvent<'a>>,
+    held: Vec<KeyboardEvent<'a>>,
+    output: String,
+    trigger: Trigger,
+    emitted: bool,
+    modifiers: Vec<u16>,
+    timing: SequenceTiming,
 }
 
 impl<'a> NeoSequenceManager<'a> {
@@ -39,22 +39,126 @@ impl<'a> NeoSequenceManager<'a> {
         }
     }
 
+    pub fn with_timing(timing: SequenceTiming) -> Self {
+        Self {
+            timing,
+            ..Default::default()
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
     pub fn receive(&mut self, event: KeyboardEvent<'a>) {
-        match event.value() {
-            KeyState::Down => {
-                self.sequence.add(event);
-            }
-            KeyState::Up => {
-                todo!()
-            }
-            KeyState::Hold => {
-                todo!()
+        self.output.clear();
+        self.trigger = Trigger::default();
+
+        self.update_modifiers(&event);
+
+        self.state = match (self.state, event.value()) {
+            (_, KeyState::Down) => self.press(event),
+            (State::Idle, _) => State::Idle,
+            (state, KeyState::Hold) => state,
+            (_, KeyState::Up) => self.release(&event),
+        };
+    }
+
+    fn press(&mut self, event: KeyboardEvent<'a>) -> State {
+        if self.held.iter().any(|e| e.key() == event.key()) {
+            return self.state;
+        }
+
+        self.emitted = false;
+        self.sequence.push(event.clone());
+        self.held.push(event);
+        State::Pressing
+    }
+
+    fn release(&mut self, event: &KeyboardEvent<'a>) -> State {
+        let Some(position) = self.held.iter().position(|e| e.key() == event.key()) else {
+            return self.state;
+        };
+        let down = self.held.remove(position);
+        let last_pressed = position == self.held.len();
+
+        // released while a key pressed after it is still held
+        if !last_pressed {
+            let held_for = event
+                .timestamp()
+                .duration_since(*down.timestamp())
+                .unwrap_or_default();
+            if held_for.as_millis() > u128::from(self.timing.orphan_window(event)) {
+                self.sequence.push(event.clone());
+            } else {
+                self.sequence.retain(|e| e.key() != event.key());
             }
         }
+
+        if matches!(self.sequence.last(), Some(e) if e.key() == event.key()) {
+            self.trigger = self.timing.produce_trigger(&self.sequence);
+            self.output = self.trigger.to_string();
+        }
+
+        if last_pressed {
+            self.sequence.retain(|e| e.key() != event.key());
+        }
+
+        if self.held.is_empty() {
+            self.sequence.clear();
+            return State::Idle;
+        }
+        State::Releasing
+    }
+
+    fn update_modifiers(&mut self, event: &KeyboardEvent) {
+        if self.emitted || self.sequence.is_empty() {
+            self.modifiers.clear();
+        } else if *event.value() == KeyState::Down && event.key().is_modifier() {
+            self.modifiers.push(event.key().code().0);
+        }
     }
+}
 
-    pub fn output(&self) -> String {
-        self.sequence.to_string()
+impl<'a> Sequencer<'a> for NeoSequenceManager<'a> {
+    fn receive(&mut self, event: KeyboardEvent<'a>) {
+        NeoSequenceManager::receive(self, event);
+    }
+
+    fn output(&self) -> &str {
+        &self.output
+    }
+
+    fn trigger(&self) -> &Trigger {
+        &self.trigger
+    }
+
+    fn current_trigger(&self) -> Trigger {
+        self.timing.produce_trigger(&self.sequence)
+    }
+
+    fn emitted(&self) -> bool {
+        self.emitted
+    }
+
+    fn set_emitted(&mut self, emitted: bool) {
+        self.emitted = emitted;
+    }
+
+    fn modifiers(&self) -> &[u16] {
+        &self.modifiers
+    }
+
+    fn first_key(&self) -> Option<&KeyIdentifier<'a>> {
+        self.sequence.first().map(KeyboardEvent::key)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    fn set_timing(&mut self, timing: SequenceTiming) {
+        self.timing = timing;
     }
 }
 
@@ -79,4 +183,36 @@ mod neo_sequence_manager_module_test {
         sm.receive(tke!(R1 J Down 0));
         assert_eq!(sm.output(), "");
     }
+
+    #[test]
+    fn moves_through_states() {
+        let (L1, R1) = mock_keyboards();
+        let mut sm = NeoSequenceManager::new();
+
+        sm.receive(tke!(L1 A Down 0));
+        assert_eq!(sm.state(), State::Pressing);
+        sm.receive(tke!(R1 J Down 100));
+        sm.receive(tke!(R1 J Hold 400));
+        assert_eq!(sm.state(), State::Pressing);
+        sm.receive(tke!(R1 J Up 450));
+        assert_eq!(sm.state(), State::Releasing);
+        sm.receive(tke!(L1 A Up 500));
+        assert_eq!(sm.state(), State::Idle);
+    }
+
+    #[test]
+    fn ignores_keys_it_never_saw_pressed() {
+        let (L1, R1) = mock_keyboards();
+        let mut sm = NeoSequenceManager::new();
+
+        sm.receive(tke!(L1 A Hold 0));
+        sm.receive(tke!(L1 A Up 10));
+        assert_eq!(sm.state(), State::Idle);
+
+        sm.receive(tke!(R1 J Down 20));
+        sm.receive(tke!(L1 A Up 30));
+        assert_eq!(sm.state(), State::Pressing);
+        sm.receive(tke!(R1 J Up 40));
+        assert_eq!(sm.output(), "R1 J");
+    }
 }
@@ -0,0 +1,202 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    config::timing::Repeat,
+    stuffs::{key_code::KeyCode, key_identifier::KeyIdentifier},
+};
+
+struct Held<'a> {
+    key: KeyIdentifier<'a>,
+    code: KeyCode,
+    /// Whether `code` is down on the virtual device yet.
+    down: bool,
+    /// When the next synthesized event is due.
+    next: Option<SystemTime>,
+}
+
+/// Repeats remapped outputs while the keys producing them are held.
+/// Like on a real keyboard, only the most recently pressed key repeats.
+pub struct Repeater<'a> {
+    repeat: Repeat,
+    held: Vec<Held<'a>>,
+}
+
+impl<'a> Repeater<'a> {
+    pub fn new(repeat: Repeat) -> Self {
+        Self {
+            repeat,
+            held: vec![],
+        }
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    /// Returns `true` when no key is tracked.
+    pub fn is_idle(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// `key` was remapped to `code`, which was pressed already.
+    pub fn press(&mut self, key: KeyIdentifier<'a>, code: KeyCode, time: SystemTime) {
+        self.start(key, code, time, true);
+    }
+
+    /// `key` completes a rule mapped to `code`, which is pressed once `key` turns out to be held.
+    pub fn watch(&mut self, key: KeyIdentifier<'a>, code: KeyCode, time: SystemTime) {
+        self.start(key, code, time, false);
+    }
+
+    fn start(&mut self, key: KeyIdentifier<'a>, code: KeyCode, time: SystemTime, down: bool) {
+        self.held.retain(|h| h.down && h.key != key);
+        for held in &mut self.held {
+            held.next = None;
+        }
+
+        let next = match self.repeat {
+            Repeat::Synthesize { delay, .. } => Some(time + Duration::from_millis(delay)),
+            Repeat::Off | Repeat::Forward => None,
+        };
+        self.held.push(Held {
+            key,
+            code,
+            down,
+            next,
+        });
+    }
+
+    /// Kernel repeat of `key`. Returns the events to emit.
+    pub fn hold(&mut self, key: &KeyIdentifier) -> Vec<(KeyCode, i32)> {
+        let Some(held) = self.held.iter_mut().find(|h| h.key == *key) else {
+            return vec![];
+        };
+
+        match self.repeat {
+            Repeat::Forward if held.down => vec![(held.code, 2)],
+            Repeat::Forward => {
+                held.down = true;
+                vec![(held.code, 1)]
+            }
+            Repeat::Off | Repeat::Synthesize { .. } => vec![],
+        }
+    }
+
+    /// Release of `key`. Returns the events to emit if its output is down, `None` otherwise.
+    pub fn release(&mut self, key: &KeyIdentifier) -> Option<Vec<(KeyCode, i32)>> {
+        let position = self.held.iter().position(|h| h.key == *key)?;
+        let held = self.held.remove(position);
+        held.down.then(|| vec![(held.code, 0)])
+    }
+
+    /// When the next synthesized event is due, if any.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.held.iter().filter_map(|h| h.next).min()
+    }
+
+    /// Synthesizes the events due at `now`.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<(KeyCode, i32)> {
+        let Repeat::Synthesize { rate, .. } = self.repeat else {
+            return vec![];
+        };
+        let interval = Duration::from_millis(1000 / rate.max(1));
+
+        let mut events = vec![];
+        for held in &mut self.held {
+            let Some(next) = held.next.filter(|n| *n <= now) else {
+                continue;
+            };
+            events.push((held.code, if held.down { 2 } else { 1 }));
+            held.down = true;
+            // skip what was missed rather than bursting to catch up
+            held.next = Some((next + interval).max(now + interval));
+        }
+        events
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod repeat_module_test {
+    use crate::{stuffs::keyboard::Keyboard, test_utilities::mipoch};
+
+    use super::*;
+
+    fn mock_keyboards() -> (Keyboard, Keyboard) {
+        let L1 = Keyboard::new("L1", "My Left Keyboard", "usb/0/0/input0");
+        let R1 = Keyboard::new("R1", "My Right Keyboard", "usb/1/1/input0");
+        (L1, R1)
+    }
+
+    fn key<'a>(device: &'a Keyboard, name: &str) -> KeyIdentifier<'a> {
+        KeyIdentifier::new(device, KeyCode::from(name).0)
+    }
+
+    #[test]
+    fn forwards_kernel_repeats() {
+        let (L1, R1) = mock_keyboards();
+        let down = KeyCode::from("Down");
+        let mut r = Repeater::new(Repeat::Forward);
+
+        r.watch(key(&R1, "J"), down, mipoch(0));
+        assert_eq!(r.hold(&key(&L1, "J")), vec![]);
+        assert_eq!(r.hold(&key(&R1, "J")), vec![(down, 1)]);
+        assert_eq!(r.hold(&key(&R1, "J")), vec![(down, 2)]);
+        assert_eq!(r.release(&key(&R1, "J")), Some(vec![(down, 0)]));
+        assert!(r.is_idle());
+    }
+
+    #[test]
+    fn watched_keys_released_before_repeating_are_plain_taps() {
+        let (_, R1) = mock_keyboards();
+        let mut r = Repeater::new(Repeat::Forward);
+
+        r.watch(key(&R1, "J"), KeyCode::from("Down"), mipoch(0));
+        assert_eq!(r.release(&key(&R1, "J")), None);
+    }
+
+    #[test]
+    fn synthesizes_repeats_at_delay_and_rate() {
+        let (_, R1) = mock_keyboards();
+        let down = KeyCode::from("Down");
+        let mut r = Repeater::new(Repeat::Synthesize {
+            delay: 250,
+            rate: 50,
+        });
+
+        r.watch(key(&R1, "J"), down, mipoch(0));
+        assert_eq!(r.hold(&key(&R1, "J")), vec![]);
+        assert_eq!(r.deadline(), Some(mipoch(250)));
+        assert_eq!(r.expire(mipoch(249)), vec![]);
+        assert_eq!(r.expire(mipoch(250)), vec![(down, 1)]);
+        assert_eq!(r.deadline(), Some(mipoch(270)));
+        assert_eq!(r.expire(mipoch(270)), vec![(down, 2)]);
+        assert_eq!(r.release(&key(&R1, "J")), Some(vec![(down, 0)]));
+        assert_eq!(r.deadline(), None);
+    }
+
+    #[test]
+    fn only_the_last_pressed_key_repeats() {
+        let (_, R1) = mock_keyboards();
+        let (left, down) = (KeyCode::from("Left"), KeyCode::from("Down"));
+        let mut r = Repeater::new(Repeat::Synthesize {
+            delay: 250,
+            rate: 50,
+        });
+        r.press(key(&R1, "H"), left, mipoch(0));
+        r.press(key(&R1, "J"), down, mipoch(100));
+        assert_eq!(r.expire(mipoch(350)), vec![(down, 2)]);
+        assert_eq!(r.release(&key(&R1, "H")), Some(vec![(left, 0)]));
+    }
+
+    #[test]
+    fn off_never_repeats() {
+        let (_, R1) = mock_keyboards();
+        let left = KeyCode::from("Left");
+        let mut r = Repeater::new(Repeat::Off);
+
+        r.press(key(&R1, "H"), left, mipoch(0));
+        assert_eq!(r.hold(&key(&R1, "H")), vec![]);
+        assert_eq!(r.release(&key(&R1, "H")), Some(vec![(left, 0)]));
+    }
+}
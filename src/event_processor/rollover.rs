@@ -0,0 +1,226 @@
+use crate::{
+    rules::trigger::{KeyPattern, Trigger},
+    stuffs::{
+        key_code::KeyCode, key_identifier::KeyIdentifier, key_state::KeyState,
+        keyboard_event::KeyboardEvent,
+    },
+};
+
+struct Deferred<'a> {
+    event: KeyboardEvent<'a>,
+    /// Modifier codes down on the virtual device when `event` happened.
+    modifiers: Vec<u16>,
+}
+
+/// Holds back keys that may still start a rule, and replays them as they were typed
+/// once they turn out not to. Events come out in their original order, so fast
+/// rolling presses keep both their order and their overlap.
+#[derive(Default)]
+pub struct Rollover<'a> {
+    events: Vec<Deferred<'a>>,
+    /// Keys to replay, whose events go out once every key pressed before them is settled.
+    decided: Vec<KeyIdentifier<'a>>,
+}
+
+impl<'a> Rollover<'a> {
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+
+    /// Returns `true` when no events are held back.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Returns `true` if events of `key` are held back.
+    pub fn holds(&self, key: &KeyIdentifier) -> bool {
+        self.events.iter().any(|d| d.event.key() == key)
+    }
+
+    /// Holds back `event`, with the modifier codes down at the time.
+    pub fn defer(&mut self, event: KeyboardEvent<'a>, modifiers: &[u16]) {
+        if *event.value() == KeyState::Hold {
+            return;
+        }
+        self.events.push(Deferred {
+            event,
+            modifiers: modifiers.to_vec(),
+        });
+    }
+
+    /// `key` didn't lead to a rule, replay it.
+    pub fn decide(&mut self, key: &KeyIdentifier<'a>) {
+        if self.holds(key) && !self.decided.contains(key) {
+            self.decided.push(key.clone());
+        }
+    }
+
+    /// `key` and the keys of `trigger` were used by a rule, drop their events.
+    pub fn discard(&mut self, key: &KeyIdentifier, trigger: &Trigger) {
+        self.events.retain(|d| {
+            let pattern = KeyPattern::from(&d.event);
+            d.event.key() != key && !trigger.keys().any(|k| k.matches(&pattern))
+        });
+        self.decided.retain(|k| k != key);
+    }
+
+    /// Takes the events that are ready to go out: everything up to the first key still undecided.
+    /// `modifiers` are the codes down on the virtual device now, which are adjusted around
+    /// each press to what they were when it happened.
+    pub fn flush(&mut self, modifiers: &[u16]) -> Vec<(KeyCode, i32)> {
+        let ready = self
+            .events
+            .iter()
+            .position(|d| !self.decided.contains(d.event.key()))
+            .unwrap_or(self.events.len());
+
+        let mut events = vec![];
+        for deferred in self.events.drain(..ready) {
+            let code = *deferred.event.key().code();
+
+            if *deferred.event.value() == KeyState::Up {
+                events.push((code, 0));
+                self.decided.retain(|k| k != deferred.event.key());
+                continue;
+            }
+
+            let pressed: Vec<u16> = deferred
+                .modifiers
+                .iter()
+                .filter(|m| !modifiers.contains(m))
+                .copied()
+                .collect();
+            let lifted: Vec<u16> = modifiers
+                .iter()
+                .filter(|m| !deferred.modifiers.contains(m))
+                .copied()
+                .collect();
+
+            events.extend(lifted.iter().map(|m| (KeyCode::from(*m), 0)));
+            events.extend(pressed.iter().map(|m| (KeyCode::from(*m), 1)));
+            events.push((code, 1));
+            events.extend(pressed.iter().map(|m| (KeyCode::from(*m), 0)));
+            events.extend(lifted.iter().map(|m| (KeyCode::from(*m), 1)));
+        }
+        events
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod rollover_module_test {
+    use crate::{stuffs::keyboard::Keyboard, tke};
+
+    use super::*;
+
+    fn mock_keyboards() -> (Keyboard, Keyboard) {
+        let L1 = Keyboard::new("L1", "My Left Keyboard", "usb/0/0/input0");
+        let R1 = Keyboard::new("R1", "My Right Keyboard", "usb/1/1/input0");
+        (L1, R1)
+    }
+
+    fn codes(events: &[(KeyCode, i32)]) -> Vec<(String, i32)> {
+        events.iter().map(|(c, v)| (c.to_string(), *v)).collect()
+    }
+
+    fn expected(events: &[(&str, i32)]) -> Vec<(String, i32)> {
+        events
+            .iter()
+            .map(|(c, v)| (KeyCode::from(*c).to_string(), *v))
+            .collect()
+    }
+
+    #[test]
+    fn rolling_keys_keep_their_order_and_overlap() {
+        let (L1, _) = mock_keyboards();
+        let mut r = Rollover::new();
+
+        // "th" with H pressed before T is released, and released after it
+        let (t, h) = (tke!(L1 T Down 0), tke!(L1 H Down 30));
+        let (t_key, h_key) = (t.key().clone(), h.key().clone());
+        r.defer(t, &[]);
+        r.defer(h, &[]);
+        r.defer(tke!(L1 T Up 60), &[]);
+        r.decide(&t_key);
+        assert_eq!(codes(&r.flush(&[])), expected(&[("T", 1)]));
+
+        r.defer(tke!(L1 H Up 90), &[]);
+        r.decide(&h_key);
+        assert_eq!(
+            codes(&r.flush(&[])),
+            expected(&[("H", 1), ("T", 0), ("H", 0)])
+        );
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn keys_released_out_of_order_come_out_in_press_order() {
+        let (L1, _) = mock_keyboards();
+        let mut r = Rollover::new();
+
+        let (t, h) = (tke!(L1 T Down 0), tke!(L1 H Down 30));
+        let (t_key, h_key) = (t.key().clone(), h.key().clone());
+        r.defer(t, &[]);
+        r.defer(h, &[]);
+        r.defer(tke!(L1 H Up 60), &[]);
+        r.decide(&h_key);
+        assert_eq!(codes(&r.flush(&[])), vec![]);
+
+        r.defer(tke!(L1 T Up 90), &[]);
+        r.decide(&t_key);
+        assert_eq!(
+            codes(&r.flush(&[])),
+            expected(&[("T", 1), ("H", 1), ("H", 0), ("T", 0)])
+        );
+    }
+
+    #[test]
+    fn keys_used_by_a_rule_are_dropped() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = Rollover::new();
+
+        let (a, j, k) = (tke!(L1 A Down 0), tke!(R1 J Down 30), tke!(R1 K Down 40));
+        let (a_key, j_key) = (a.key().clone(), j.key().clone());
+        r.defer(a, &[]);
+        r.defer(j, &[]);
+        r.defer(tke!(L1 A Up 50), &[]);
+        r.decide(&a_key);
+        r.defer(k, &[]);
+        r.defer(tke!(R1 J Up 80), &[]);
+        r.discard(&j_key, &"R1 J, R1 K".parse().unwrap());
+
+        assert_eq!(codes(&r.flush(&[])), expected(&[("A", 1), ("A", 0)]));
+        assert!(!r.holds(&j_key));
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn modifiers_are_restored_to_what_they_were_at_press_time() {
+        let (L1, _) = mock_keyboards();
+        let mut r = Rollover::new();
+        let shift = KeyCode::from("LeftShift").0;
+
+        // J pressed before Shift, released after it
+        let j = tke!(L1 J Down 0);
+        let j_key = j.key().clone();
+        r.defer(j, &[]);
+        r.defer(tke!(L1 J Up 100), &[shift]);
+        r.decide(&j_key);
+        assert_eq!(
+            codes(&r.flush(&[shift])),
+            expected(&[("LeftShift", 0), ("J", 1), ("LeftShift", 1), ("J", 0)])
+        );
+
+        // J pressed with Shift, which was let go before J
+        let j = tke!(L1 J Down 200);
+        r.defer(j, &[shift]);
+        r.defer(tke!(L1 J Up 300), &[]);
+        r.decide(&j_key);
+        assert_eq!(
+            codes(&r.flush(&[])),
+            expected(&[("LeftShift", 1), ("J", 1), ("LeftShift", 0), ("J", 0)])
+        );
+    }
+}
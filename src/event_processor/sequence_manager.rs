@@ -1,6 +1,105 @@
-use std::fmt::Display;
+use std::collections::HashMap;
+
+use crate::{
+    config::timing::{Timing, Windows},
+    rules::{
+        trigger::{KeyPattern, Step, Trigger},
+        Ruleset,
+    },
+    stuffs::{
+        key_identifier::KeyIdentifier, key_state::KeyState, keyboard::Keyboard,
+        keyboard_event::KeyboardEvent,
+    },
+};
+
+use super::sequencer::Sequencer;
+
+/// Chord and orphan windows, in ms, resolved from the most specific place they are set:
+/// the rule, then the keyboard, then the global `Timing`.
+#[derive(Debug, Clone)]
+pub struct SequenceTiming {
+    chord_window: u64,
+    orphan_window: u64,
+    devices: HashMap<String, Windows>,
+    rules: Vec<(Trigger, Windows)>,
+}
+
+impl Default for SequenceTiming {
+    fn default() -> Self {
+        Self::new(&Timing::default(), &[], &Ruleset::new())
+    }
+}
+
+impl SequenceTiming {
+    pub fn new(timing: &Timing, keyboards: &[Keyboard], ruleset: &Ruleset) -> Self {
+        Self {
+            chord_window: *timing.chord_window(),
+            orphan_window: *timing.orphan_window(),
+            devices: keyboards
+                .iter()
+                .map(|k| (k.alias().clone(), *k.timing()))
+                .collect(),
+            rules: ruleset.timed_triggers(),
+        }
+    }
 
-use crate::stuffs::{key_state::KeyState, keyboard_event::KeyboardEvent};
+    fn device(&self, event: &KeyboardEvent) -> Option<&Windows> {
+        self.devices.get(event.key().device().alias())
+    }
+
+    fn chord_window(&self, event: &KeyboardEvent) -> u64 {
+        self.device(event)
+            .and_then(|w| *w.chord_window())
+            .unwrap_or(self.chord_window)
+    }
+
+    /// A rule's orphan window applies to the keys it expects to be released.
+    pub fn orphan_window(&self, event: &KeyboardEvent) -> u64 {
+        let released = KeyPattern::from(event);
+        self.rules
+            .iter()
+            .filter(|(trigger, _)| trigger.keys().any(|k| k.matches(&released)))
+            .find_map(|(_, w)| *w.orphan_window())
+            .or_else(|| self.device(event).and_then(|w| *w.orphan_window()))
+            .unwrap_or(self.orphan_window)
+    }
+
+    /// Groups `sequence` with the chord windows of the keyboards.
+    /// Rules with their own chord window are tried first, in the order they were declared,
+    /// and win if the sequence grouped with their window matches them.
+    pub fn produce_trigger(&self, sequence: &[KeyboardEvent]) -> Trigger {
+        for (trigger, windows) in &self.rules {
+            if let Some(window) = windows.chord_window() {
+                let grouped = group(sequence, |_| *window);
+                if trigger.matches(&grouped) {
+                    return grouped;
+                }
+            }
+        }
+        group(sequence, |e| self.chord_window(e))
+    }
+}
+
+/// Groups `sequence` into steps, where events at most
+/// `chord_window` ms after the start of a step form a chord.
+fn group(sequence: &[KeyboardEvent], chord_window: impl Fn(&KeyboardEvent) -> u64) -> Trigger {
+    let mut steps: Vec<Vec<KeyPattern>> = vec![];
+    let Some(first) = sequence.first() else {
+        return Trigger::default();
+    };
+    let mut last_time = first.timestamp();
+
+    for e in sequence {
+        let elapsed = e.timestamp().duration_since(*last_time).unwrap_or_default();
+        if steps.is_empty() || elapsed.as_millis() > u128::from(chord_window(e)) {
+            steps.push(vec![]);
+            last_time = e.timestamp();
+        }
+        steps.last_mut().unwrap().push(KeyPattern::from(e));
+    }
+
+    Trigger::new(steps.into_iter().map(Step::new).collect())
+}
 
 #[derive(Getters, Setters)]
 pub struct SequenceManager<'a> {
@@ -10,6 +109,10 @@ pub struct SequenceManager<'a> {
     #[getset(get = "pub")]
     output: String,
 
+    /// Structured form of `output`, used to match rules.
+    #[getset(get = "pub")]
+    trigger: Trigger,
+
     #[getset(get = "pub", set = "pub")]
     emitted: bool,
 
@@ -17,21 +120,31 @@ pub struct SequenceManager<'a> {
     modifiers: Vec<u16>,
 
     currently_down_events: Vec<KeyboardEvent<'a>>,
+
+    #[getset(set = "pub")]
+    timing: SequenceTiming,
 }
 
 impl<'a> SequenceManager<'a> {
     pub fn new() -> Self {
+        Self::with_timing(SequenceTiming::default())
+    }
+
+    pub fn with_timing(timing: SequenceTiming) -> Self {
         Self {
             sequence: vec![],
             output: String::new(),
+            trigger: Trigger::default(),
             emitted: false,
             modifiers: vec![],
             currently_down_events: vec![],
+            timing,
         }
     }
 
     pub fn receive(&mut self, event: KeyboardEvent<'a>) {
         self.output.clear();
+        self.trigger = Trigger::default();
 
         self.update_modifiers(&event);
 
@@ -43,6 +156,14 @@ impl<'a> SequenceManager<'a> {
                 self.currently_down_events.push(event);
             }
             KeyState::Up => {
+                // released without being pressed since we started, like the key that launched us
+                if !self
+                    .currently_down_events
+                    .iter()
+                    .any(|e| e.key() == event.key())
+                {
+                    return;
+                }
                 let last_down_key = self.currently_down_events.last().unwrap().key().clone();
 
                 self.handle_orphan_event(&event, &last_down_key);
@@ -81,7 +202,7 @@ impl<'a> SequenceManager<'a> {
             .duration_since(*orphan_counterpart.timestamp())
             .unwrap()
             .as_millis()
-            > 150
+            > u128::from(self.timing.orphan_window(event))
     }
 
     fn key_up_event_cleanup(
@@ -102,7 +223,8 @@ impl<'a> SequenceManager<'a> {
     fn update_output(&mut self, event: &KeyboardEvent) {
         let last_sequence_key = self.sequence.last().unwrap().key();
         if event.key() == last_sequence_key {
-            self.output.push_str(&self.produce_output());
+            self.trigger = self.timing.produce_trigger(&self.sequence);
+            self.output = self.trigger.to_string();
         }
     }
 
@@ -114,16 +236,24 @@ impl<'a> SequenceManager<'a> {
         0
     }
 
+    /// The sequence so far, grouped the same way `trigger` is once it completes.
+    pub fn current_trigger(&self) -> Trigger {
+        self.timing.produce_trigger(&self.sequence)
+    }
+
+    /// Returns `true` when no keys are currently held down.
+    pub fn is_idle(&self) -> bool {
+        self.currently_down_events.is_empty()
+    }
+
     pub fn is_combined(&self) -> bool {
         self.output.contains(',')
     }
 
     fn update_modifiers(&mut self, event: &KeyboardEvent) {
-        let modifier_codes: Vec<u16> = vec![14, 29, 42, 54, 56, 97, 100, 125, 126];
-
         if self.emitted || self.sequence.is_empty() {
             self.modifiers.clear();
-        } else if modifier_codes.contains(&event.key().code().0) {
+        } else if *event.value() == KeyState::Down && event.key().is_modifier() {
             self.modifiers.push(event.key().code().0);
         }
     }
@@ -131,67 +261,200 @@ impl<'a> SequenceManager<'a> {
     fn add_event(&mut self, event: KeyboardEvent<'a>) {
         self.sequence.push(event);
     }
+}
 
-    fn produce_output(&self) -> String {
-        {
-            let interval_limit = 50;
-            let mut breakpoints: Vec<usize> = vec![0];
-            let mut last_time = self.sequence.first().unwrap().timestamp();
+impl<'a> Sequencer<'a> for SequenceManager<'a> {
+    fn receive(&mut self, event: KeyboardEvent<'a>) {
+        SequenceManager::receive(self, event);
+    }
 
-            for (i, e) in self.sequence.iter().enumerate() {
-                if let Ok(duration) = e.timestamp().duration_since(*last_time) {
-                    if duration.as_millis() > interval_limit {
-                        breakpoints.push(i);
-                        last_time = e.timestamp();
-                    }
-                }
-            }
+    fn output(&self) -> &str {
+        &self.output
+    }
+
+    fn trigger(&self) -> &Trigger {
+        &self.trigger
+    }
 
-            // ---------------------------------- refactor me please
+    fn current_trigger(&self) -> Trigger {
+        SequenceManager::current_trigger(self)
+    }
 
-            let mut unions = vec![];
+    fn emitted(&self) -> bool {
+        self.emitted
+    }
 
-            for (i, e) in self.sequence.iter().enumerate() {
-                if breakpoints.contains(&i) {
-                    unions.push(Union(vec![e]));
-                } else {
-                    unions.last_mut().unwrap().0.push(e);
-                }
-            }
+    fn set_emitted(&mut self, emitted: bool) {
+        self.emitted = emitted;
+    }
 
-            unions
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<String>>()
-                .join(", ")
-        }
+    fn modifiers(&self) -> &[u16] {
+        &self.modifiers
     }
-}
 
-enum SequenceElement<'a> {
-    Union(Union<'a>),
-    Event(KeyboardEvent<'a>),
+    fn first_key(&self) -> Option<&KeyIdentifier<'a>> {
+        self.sequence.first().map(KeyboardEvent::key)
+    }
+
+    fn is_idle(&self) -> bool {
+        SequenceManager::is_idle(self)
+    }
+
+    fn set_timing(&mut self, timing: SequenceTiming) {
+        self.timing = timing;
+    }
 }
 
-struct Union<'a>(Vec<&'a KeyboardEvent<'a>>);
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod sequence_manager_module_test {
+    use crate::{config::Config, tke};
+
+    use super::*;
+
+    fn mock_config(timing: &str, rules: &str) -> Config {
+        serde_json::from_str(&format!(
+            r#"{{
+                "keyboards": [
+                    {{ "alias": "L1", "name": "My Left Keyboard", "path": "usb/0/0/input0" }},
+                    {{ "alias": "R1", "name": "My Right Keyboard", "path": "usb/1/1/input0",
+                       "timing": {{ "chord_window": 100, "orphan_window": 300 }} }}
+                ],
+                "timing": {{ {timing} }},
+                "rules": {{ {rules} }}
+            }}"#
+        ))
+        .unwrap()
+    }
 
-impl<'a> Display for Union<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.0.len() == 1 {
-            return write!(f, "{}", self.0.first().unwrap());
-        }
+    fn mock_sm<'a>(config: &Config) -> SequenceManager<'a> {
+        SequenceManager::with_timing(SequenceTiming::new(
+            config.timing(),
+            config.keyboards(),
+            config.rules(),
+        ))
+    }
 
-        let mut sorted_union = self.0.clone();
-        sorted_union.sort_by_key(|e| e.key());
+    #[test]
+    fn chord_window_boundary() {
+        let config = mock_config("", "");
+        let (L1, R1) = (&config.keyboards()[0], &config.keyboards()[1]);
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(R1 A Down 0));
+        sm.receive(tke!(L1 B Down 50));
+        sm.receive(tke!(L1 B Up 80));
+        assert_eq!(sm.output(), "[L1 B, R1 A]");
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(R1 A Down 0));
+        sm.receive(tke!(L1 B Down 51));
+        sm.receive(tke!(L1 B Up 80));
+        assert_eq!(sm.output(), "R1 A, L1 B");
+    }
 
-        write!(
-            f,
-            "[{}]",
-            sorted_union
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<String>>()
-                .join(", ")
-        )
+    #[test]
+    fn chord_window_is_configurable_globally() {
+        let config = mock_config(r#""chord_window": 20"#, "");
+        let L1 = &config.keyboards()[0];
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(L1 B Down 20));
+        sm.receive(tke!(L1 B Up 40));
+        assert_eq!(sm.output(), "[L1 A, L1 B]");
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(L1 B Down 21));
+        sm.receive(tke!(L1 B Up 40));
+        assert_eq!(sm.output(), "L1 A, L1 B");
+    }
+
+    #[test]
+    fn chord_window_is_configurable_per_device() {
+        let config = mock_config("", "");
+        let (L1, R1) = (&config.keyboards()[0], &config.keyboards()[1]);
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(R1 B Down 100));
+        sm.receive(tke!(R1 B Up 120));
+        assert_eq!(sm.output(), "[L1 A, R1 B]");
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(R1 B Down 101));
+        sm.receive(tke!(R1 B Up 120));
+        assert_eq!(sm.output(), "L1 A, R1 B");
+    }
+
+    #[test]
+    fn chord_window_is_configurable_per_rule() {
+        let rules =
+            r#""[L1 A, L1 B]": { "output": { "Map": "C" }, "timing": { "chord_window": 80 } }"#;
+        let config = mock_config("", rules);
+        let L1 = &config.keyboards()[0];
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(L1 B Down 80));
+        sm.receive(tke!(L1 B Up 100));
+        assert_eq!(sm.output(), "[L1 A, L1 B]");
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(L1 B Down 81));
+        sm.receive(tke!(L1 B Up 100));
+        assert_eq!(sm.output(), "L1 A, L1 B");
+
+        // other sequences keep the global window
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(L1 C Down 80));
+        sm.receive(tke!(L1 C Up 100));
+        assert_eq!(sm.output(), "L1 A, L1 C");
+    }
+
+    #[test]
+    fn orphan_window_boundary() {
+        let config = mock_config("", "");
+        let L1 = &config.keyboards()[0];
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(L1 B Down 100));
+        sm.receive(tke!(L1 A Up 151));
+        assert_eq!(sm.output(), "L1 A, L1 B, !L1 A");
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(L1 A Down 0));
+        sm.receive(tke!(L1 B Down 100));
+        sm.receive(tke!(L1 A Up 150));
+        sm.receive(tke!(L1 B Up 200));
+        assert_eq!(sm.output(), "L1 B");
+    }
+
+    #[test]
+    fn orphan_window_is_configurable_per_device_and_rule() {
+        let config = mock_config("", "");
+        let R1 = &config.keyboards()[1];
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(R1 A Down 0));
+        sm.receive(tke!(R1 B Down 150));
+        sm.receive(tke!(R1 A Up 300));
+        sm.receive(tke!(R1 B Up 400));
+        assert_eq!(sm.output(), "R1 B");
+
+        let rules = r#""R1 A, R1 B, !R1 A": { "output": { "Map": "C" }, "timing": { "orphan_window": 250 } }"#;
+        let config = mock_config("", rules);
+        let R1 = &config.keyboards()[1];
+
+        let mut sm = mock_sm(&config);
+        sm.receive(tke!(R1 A Down 0));
+        sm.receive(tke!(R1 B Down 150));
+        sm.receive(tke!(R1 A Up 251));
+        assert_eq!(sm.output(), "R1 A, R1 B, !R1 A");
     }
 }
@@ -0,0 +1,226 @@
+use serde::Deserialize;
+
+use crate::{
+    rules::trigger::Trigger,
+    stuffs::{key_identifier::KeyIdentifier, keyboard_event::KeyboardEvent},
+};
+
+use super::{
+    neo_sequence_manager::NeoSequenceManager,
+    sequence_manager::{SequenceManager, SequenceTiming},
+};
+
+/// What the interceptor needs from a sequence engine.
+/// Both `SequenceManager` and `NeoSequenceManager` implement it,
+/// and are held to the same behavior by the conformance tests below.
+pub trait Sequencer<'a> {
+    fn receive(&mut self, event: KeyboardEvent<'a>);
+
+    /// The completed sequence, set on the release that completes it and cleared on the next event.
+    fn output(&self) -> &str;
+
+    /// Structured form of `output`, used to match rules.
+    fn trigger(&self) -> &Trigger;
+
+    /// The sequence so far, grouped the same way `trigger` is once it completes.
+    fn current_trigger(&self) -> Trigger;
+
+    fn emitted(&self) -> bool;
+
+    fn set_emitted(&mut self, emitted: bool);
+
+    /// Modifier codes pressed since the sequence started, while nothing was emitted.
+    fn modifiers(&self) -> &[u16];
+
+    fn first_key(&self) -> Option<&KeyIdentifier<'a>>;
+
+    fn is_combined(&self) -> bool {
+        self.output().contains(',')
+    }
+
+    /// Returns `true` when no keys are currently held down.
+    fn is_idle(&self) -> bool;
+
+    fn set_timing(&mut self, timing: SequenceTiming);
+}
+
+/// Which `Sequencer` the interceptor runs, picked with `"sequencer"` in the config.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SequencerKind {
+    #[default]
+    Classic,
+    Neo,
+}
+
+impl SequencerKind {
+    pub fn build<'a>(self, timing: SequenceTiming) -> Box<dyn Sequencer<'a> + 'a> {
+        match self {
+            SequencerKind::Classic => Box::new(SequenceManager::with_timing(timing)),
+            SequencerKind::Neo => Box::new(NeoSequenceManager::with_timing(timing)),
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod sequencer_conformance_test {
+    use crate::{stuffs::keyboard::Keyboard, tke};
+
+    use super::*;
+
+    fn mock_keyboards() -> (Keyboard, Keyboard) {
+        let L1 = Keyboard::new("L1", "My Left Keyboard", "usb/0/0/input0");
+        let R1 = Keyboard::new("R1", "My Right Keyboard", "usb/1/1/input0");
+        (L1, R1)
+    }
+
+    fn sequencers<'a>() -> Vec<(SequencerKind, Box<dyn Sequencer<'a> + 'a>)> {
+        [SequencerKind::Classic, SequencerKind::Neo]
+            .into_iter()
+            .map(|kind| (kind, kind.build(SequenceTiming::default())))
+            .collect()
+    }
+
+    #[test]
+    fn single_key() {
+        let (_, R1) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(R1 J Down 0));
+            assert_eq!(sm.output(), "", "{kind:?}");
+            assert!(!sm.is_idle(), "{kind:?}");
+
+            sm.receive(tke!(R1 J Up 100));
+            assert_eq!(sm.output(), "R1 J", "{kind:?}");
+            assert!(!sm.is_combined(), "{kind:?}");
+            assert!(sm.is_idle(), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn sequence_of_keys_shares_its_held_prefix() {
+        let (L1, R1) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(L1 CAPSLOCK Down 0));
+            sm.receive(tke!(R1 J Down 100));
+            assert_eq!(
+                sm.current_trigger().to_string(),
+                "L1 CAPSLOCK, R1 J",
+                "{kind:?}"
+            );
+            sm.receive(tke!(R1 J Up 150));
+            assert_eq!(sm.output(), "L1 CAPSLOCK, R1 J", "{kind:?}");
+            assert!(sm.is_combined(), "{kind:?}");
+
+            sm.receive(tke!(R1 K Down 200));
+            assert_eq!(sm.output(), "", "{kind:?}");
+            sm.receive(tke!(R1 K Up 250));
+            assert_eq!(sm.output(), "L1 CAPSLOCK, R1 K", "{kind:?}");
+            assert_eq!(sm.first_key().map(|k| k.code().0), Some(58), "{kind:?}");
+
+            sm.receive(tke!(L1 CAPSLOCK Up 300));
+            assert_eq!(sm.output(), "L1 CAPSLOCK", "{kind:?}");
+            assert!(sm.is_idle(), "{kind:?}");
+            assert_eq!(sm.first_key(), None, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn chords() {
+        let (L1, R1) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(L1 A Down 0));
+            sm.receive(tke!(R1 B Down 30));
+            sm.receive(tke!(R1 B Up 100));
+            assert_eq!(sm.output(), "[L1 A, R1 B]", "{kind:?}");
+            assert_eq!(sm.trigger().to_string(), "[L1 A, R1 B]", "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn orphans() {
+        let (L1, _) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(L1 A Down 0));
+            sm.receive(tke!(L1 B Down 100));
+            sm.receive(tke!(L1 A Up 151));
+            assert_eq!(sm.output(), "L1 A, L1 B, !L1 A", "{kind:?}");
+            sm.receive(tke!(L1 B Up 200));
+            assert_eq!(sm.output(), "", "{kind:?}");
+            assert!(sm.is_idle(), "{kind:?}");
+
+            sm.receive(tke!(L1 A Down 1000));
+            sm.receive(tke!(L1 B Down 1100));
+            sm.receive(tke!(L1 A Up 1150));
+            assert_eq!(sm.output(), "", "{kind:?}");
+            sm.receive(tke!(L1 B Up 1200));
+            assert_eq!(sm.output(), "L1 B", "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn releases_of_keys_never_pressed_are_ignored() {
+        let (L1, R1) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(L1 ENTER Up 0));
+            assert_eq!(sm.output(), "", "{kind:?}");
+            assert!(sm.is_idle(), "{kind:?}");
+
+            sm.receive(tke!(R1 J Down 100));
+            sm.receive(tke!(L1 ENTER Up 150));
+            assert_eq!(sm.current_trigger().to_string(), "R1 J", "{kind:?}");
+            sm.receive(tke!(R1 J Up 200));
+            assert_eq!(sm.output(), "R1 J", "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn repeats_leave_the_sequence_alone() {
+        let (L1, _) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(L1 A Down 0));
+            sm.receive(tke!(L1 A Hold 300));
+            sm.receive(tke!(L1 A Hold 330));
+            assert_eq!(sm.output(), "", "{kind:?}");
+            assert_eq!(sm.current_trigger().to_string(), "L1 A", "{kind:?}");
+
+            sm.receive(tke!(L1 A Up 400));
+            assert_eq!(sm.output(), "L1 A", "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn emitted_and_modifiers() {
+        let (L1, R1) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(R1 J Down 0));
+            sm.receive(tke!(L1 LEFTSHIFT Down 100));
+            sm.receive(tke!(R1 K Down 200));
+            assert_eq!(sm.modifiers(), &[42], "{kind:?}");
+
+            sm.receive(tke!(R1 K Up 250));
+            sm.set_emitted(true);
+            sm.receive(tke!(L1 LEFTSHIFT Up 300));
+            assert!(sm.emitted(), "{kind:?}");
+            assert!(sm.modifiers().is_empty(), "{kind:?}");
+
+            sm.receive(tke!(R1 L Down 400));
+            assert!(!sm.emitted(), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn modifiers_released_mid_sequence_count_once() {
+        let (L1, R1) = mock_keyboards();
+        for (kind, mut sm) in sequencers() {
+            sm.receive(tke!(R1 J Down 0));
+            sm.receive(tke!(L1 LEFTSHIFT Down 100));
+            sm.receive(tke!(L1 LEFTSHIFT Hold 400));
+            sm.receive(tke!(L1 LEFTSHIFT Up 450));
+            assert_eq!(sm.modifiers(), &[42], "{kind:?}");
+
+            sm.receive(tke!(R1 K Down 500));
+            sm.receive(tke!(R1 K Up 550));
+            assert_eq!(sm.modifiers(), &[42], "{kind:?}");
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    interceptor::rule_output::{Hold, TapHold},
+    rules::trigger::KeyPattern,
+    stuffs::{
+        key_code::KeyCode, key_identifier::KeyIdentifier, key_state::KeyState,
+        keyboard_event::KeyboardEvent,
+    },
+};
+
+/// What comes out of the `TapHoldResolver`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolved<'a> {
+    /// An event tap-hold doesn't care about, to be processed as usual.
+    Event(KeyboardEvent<'a>),
+    /// Key events to emit as they are.
+    Emit(Vec<(KeyCode, i32)>),
+    /// A layer held by a tap-hold key goes on or off.
+    Layer(String, bool),
+}
+
+impl Resolved<'_> {
+    fn hold(hold: &Hold, on: bool) -> Self {
+        match hold {
+            Hold::Key(code) => Resolved::Emit(vec![(*code, i32::from(on))]),
+            Hold::Layer { layer } => Resolved::Layer(layer.clone(), on),
+        }
+    }
+}
+
+struct Undecided<'a> {
+    key: KeyIdentifier<'a>,
+    tap_hold: TapHold,
+    since: SystemTime,
+    buffer: Vec<KeyboardEvent<'a>>,
+}
+
+impl Undecided<'_> {
+    fn deadline(&self) -> SystemTime {
+        self.since + Duration::from_millis(*self.tap_hold.tapping_term())
+    }
+}
+
+/// Decides whether dual-role keys are tapped or held.
+/// While a key is undecided, other events are buffered, then replayed
+/// once the decision is made, so they see the hold key if it was held.
+#[derive(Default)]
+pub struct TapHoldResolver<'a> {
+    keys: Vec<(KeyPattern, TapHold)>,
+    undecided: Option<Undecided<'a>>,
+    held: Vec<(KeyIdentifier<'a>, Hold)>,
+}
+
+impl<'a> TapHoldResolver<'a> {
+    pub fn new(keys: Vec<(KeyPattern, TapHold)>) -> Self {
+        Self {
+            keys,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_keys(&mut self, keys: Vec<(KeyPattern, TapHold)>) {
+        self.keys = keys;
+    }
+
+    /// Returns `true` when no tap-hold key is undecided or held.
+    pub fn is_idle(&self) -> bool {
+        self.undecided.is_none() && self.held.is_empty()
+    }
+
+    /// When the undecided key, if any, turns into a hold.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.undecided.as_ref().map(Undecided::deadline)
+    }
+
+    pub fn receive(&mut self, event: KeyboardEvent<'a>) -> Vec<Resolved<'a>> {
+        let mut resolved = vec![];
+        if matches!(self.deadline(), Some(d) if *event.timestamp() >= d) {
+            resolved.append(&mut self.decide(true));
+        }
+
+        let Some(undecided) = &mut self.undecided else {
+            resolved.append(&mut self.receive_idle(event));
+            return resolved;
+        };
+
+        if *event.key() == undecided.key {
+            if *event.value() == KeyState::Up {
+                resolved.append(&mut self.decide(false));
+            }
+            return resolved;
+        }
+
+        let hold = match event.value() {
+            KeyState::Down => *undecided.tap_hold.hold_on_other_key_press(),
+            KeyState::Up => {
+                *undecided.tap_hold.permissive_hold()
+                    && undecided.buffer.iter().any(|e| e.key() == event.key())
+            }
+            KeyState::Hold => false,
+        };
+        undecided.buffer.push(event);
+
+        if hold {
+            resolved.append(&mut self.decide(true));
+        }
+        resolved
+    }
+
+    fn receive_idle(&mut self, event: KeyboardEvent<'a>) -> Vec<Resolved<'a>> {
+        if let Some(i) = self.held.iter().position(|(key, _)| key == event.key()) {
+            if *event.value() == KeyState::Up {
+                let (_, hold) = self.held.remove(i);
+                return vec![Resolved::hold(&hold, false)];
+            }
+            return vec![];
+        }
+
+        if *event.value() == KeyState::Down {
+            let pattern = KeyPattern::from(&event);
+            if let Some((_, tap_hold)) = self.keys.iter().find(|(k, _)| k.matches(&pattern)) {
+                self.undecided = Some(Undecided {
+                    key: event.key().clone(),
+                    tap_hold: tap_hold.clone(),
+                    since: *event.timestamp(),
+                    buffer: vec![],
+                });
+                return vec![];
+            }
+        }
+
+        vec![Resolved::Event(event)]
+    }
+
+    /// Settles the undecided key, then replays whatever was buffered meanwhile.
+    fn decide(&mut self, hold: bool) -> Vec<Resolved<'a>> {
+        let Some(undecided) = self.undecided.take() else {
+            return vec![];
+        };
+
+        let mut resolved = if hold {
+            let hold = undecided.tap_hold.hold().clone();
+            let resolved = vec![Resolved::hold(&hold, true)];
+            self.held.push((undecided.key, hold));
+            resolved
+        } else {
+            let tap = *undecided.tap_hold.tap();
+            vec![Resolved::Emit(vec![(tap, 1), (tap, 0)])]
+        };
+
+        for event in undecided.buffer {
+            resolved.append(&mut self.receive(event));
+        }
+        resolved
+    }
+
+    /// The key waiting to be decided, if any.
+    pub fn undecided(&self) -> Option<&KeyIdentifier<'a>> {
+        self.undecided.as_ref().map(|u| &u.key)
+    }
+
+    /// Drops the undecided key without tapping or holding it, for when its keyboard went away,
+    /// then replays whatever was buffered meanwhile.
+    pub fn forget(&mut self) -> Vec<Resolved<'a>> {
+        let Some(undecided) = self.undecided.take() else {
+            return vec![];
+        };
+
+        let mut resolved = vec![];
+        for event in undecided.buffer {
+            resolved.append(&mut self.receive(event));
+        }
+        resolved
+    }
+
+    /// Turns the undecided key into a hold once `now` is past its tapping term.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Resolved<'a>> {
+        if matches!(self.deadline(), Some(d) if now >= d) {
+            return self.decide(true);
+        }
+        vec![]
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod tap_hold_module_test {
+    use crate::{stuffs::keyboard::Keyboard, test_utilities::mipoch, tke};
+
+    use super::*;
+
+    fn mock_keyboards() -> (Keyboard, Keyboard) {
+        let L1 = Keyboard::new("L1", "My Left Keyboard", "usb/0/0/input0");
+        let R1 = Keyboard::new("R1", "My Right Keyboard", "usb/1/1/input0");
+        (L1, R1)
+    }
+
+    fn resolver<'a>(options: &str) -> TapHoldResolver<'a> {
+        let tap_hold = serde_json::from_str(&format!(
+            r#"{{ "tap": "Esc", "hold": "LeftCtrl", "tapping_term": 200 {options} }}"#
+        ))
+        .unwrap();
+        TapHoldResolver::new(vec![(KeyPattern::new(Some("L1"), "CAPSLOCK"), tap_hold)])
+    }
+
+    fn tap() -> Resolved<'static> {
+        Resolved::Emit(vec![(KeyCode::from("Esc"), 1), (KeyCode::from("Esc"), 0)])
+    }
+
+    fn hold(value: i32) -> Resolved<'static> {
+        Resolved::Emit(vec![(KeyCode::from("LeftCtrl"), value)])
+    }
+
+    #[test]
+    fn quick_release_is_a_tap() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = resolver("");
+
+        assert_eq!(r.receive(tke!(L1 CAPSLOCK Down 0)), vec![]);
+        assert_eq!(r.receive(tke!(L1 CAPSLOCK Up 100)), vec![tap()]);
+        assert_eq!(
+            r.receive(tke!(R1 J Down 150)),
+            vec![Resolved::Event(tke!(R1 J Down 150))]
+        );
+        assert!(r.is_idle());
+    }
+
+    #[test]
+    fn holding_past_tapping_term_is_a_hold() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = resolver("");
+
+        r.receive(tke!(L1 CAPSLOCK Down 0));
+        assert_eq!(r.deadline(), Some(mipoch(200)));
+        assert_eq!(r.expire(mipoch(199)), vec![]);
+        assert_eq!(r.expire(mipoch(200)), vec![hold(1)]);
+
+        assert_eq!(r.receive(tke!(L1 CAPSLOCK Hold 230)), vec![]);
+        assert_eq!(
+            r.receive(tke!(R1 J Down 250)),
+            vec![Resolved::Event(tke!(R1 J Down 250))]
+        );
+        assert_eq!(r.receive(tke!(L1 CAPSLOCK Up 300)), vec![hold(0)]);
+    }
+
+    #[test]
+    fn other_keys_wait_for_the_decision() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = resolver("");
+
+        r.receive(tke!(L1 CAPSLOCK Down 0));
+        assert_eq!(r.receive(tke!(R1 J Down 50)), vec![]);
+        assert_eq!(r.receive(tke!(R1 J Up 80)), vec![]);
+        assert_eq!(
+            r.receive(tke!(L1 CAPSLOCK Up 100)),
+            vec![
+                tap(),
+                Resolved::Event(tke!(R1 J Down 50)),
+                Resolved::Event(tke!(R1 J Up 80)),
+            ]
+        );
+    }
+
+    #[test]
+    fn late_events_settle_the_hold_first() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = resolver("");
+
+        r.receive(tke!(L1 CAPSLOCK Down 0));
+        assert_eq!(
+            r.receive(tke!(R1 J Down 250)),
+            vec![hold(1), Resolved::Event(tke!(R1 J Down 250))]
+        );
+    }
+
+    #[test]
+    fn permissive_hold() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = resolver(r#", "permissive_hold": true"#);
+
+        r.receive(tke!(L1 CAPSLOCK Down 0));
+        assert_eq!(r.receive(tke!(R1 J Down 50)), vec![]);
+        assert_eq!(
+            r.receive(tke!(R1 J Up 80)),
+            vec![
+                hold(1),
+                Resolved::Event(tke!(R1 J Down 50)),
+                Resolved::Event(tke!(R1 J Up 80)),
+            ]
+        );
+        assert_eq!(r.receive(tke!(L1 CAPSLOCK Up 100)), vec![hold(0)]);
+    }
+
+    #[test]
+    fn hold_on_other_key_press() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = resolver(r#", "hold_on_other_key_press": true"#);
+
+        r.receive(tke!(L1 CAPSLOCK Down 0));
+        assert_eq!(
+            r.receive(tke!(R1 J Down 50)),
+            vec![hold(1), Resolved::Event(tke!(R1 J Down 50))]
+        );
+    }
+
+    #[test]
+    fn forgotten_keys_are_neither_tapped_nor_held() {
+        let (L1, R1) = mock_keyboards();
+        let mut r = resolver("");
+
+        r.receive(tke!(L1 CAPSLOCK Down 0));
+        assert_eq!(r.receive(tke!(R1 J Down 50)), vec![]);
+        assert_eq!(r.undecided(), Some(&KeyIdentifier::new(&L1, "CAPSLOCK")));
+        assert_eq!(r.forget(), vec![Resolved::Event(tke!(R1 J Down 50))]);
+        assert_eq!(r.deadline(), None);
+        assert!(r.is_idle());
+    }
+
+    #[test]
+    fn can_hold_a_layer() {
+        let (L1, _) = mock_keyboards();
+        let tap_hold =
+            serde_json::from_str(r#"{ "tap": "Esc", "hold": { "layer": "nav" } }"#).unwrap();
+        let mut r = TapHoldResolver::new(vec![(KeyPattern::new(Some("L1"), "CAPSLOCK"), tap_hold)]);
+
+        r.receive(tke!(L1 CAPSLOCK Down 0));
+        assert_eq!(
+            r.expire(mipoch(200)),
+            vec![Resolved::Layer("nav".into(), true)]
+        );
+        assert_eq!(
+            r.receive(tke!(L1 CAPSLOCK Up 300)),
+            vec![Resolved::Layer("nav".into(), false)]
+        );
+    }
+}
@@ -15,18 +15,28 @@ struct NeovimInstance {
 
 pub fn start_server(tx: Sender<TransmitSignal>) {
     thread::spawn(move || {
-        let listener = TcpListener::bind("0.0.0.0:3333").unwrap();
+        let listener = match TcpListener::bind("0.0.0.0:3333") {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!("Neovim integration is off, can't listen on port 3333: {err}");
+                return;
+            }
+        };
 
-        for stream in listener.incoming() {
-            let port = handle_stream(stream.unwrap());
-            tx.send(TransmitSignal::NeovimTCPPort(port)).unwrap();
+        for stream in listener.incoming().flatten() {
+            let Some(port) = handle_stream(stream) else {
+                continue;
+            };
+            if tx.send(TransmitSignal::NeovimTCPPort(port)).is_err() {
+                break;
+            }
         }
     });
 }
 
-fn handle_stream(mut stream: TcpStream) -> String {
+fn handle_stream(mut stream: TcpStream) -> Option<String> {
     let mut buffer = [0; 32];
-    let read = stream.read(&mut buffer).unwrap();
+    let read = stream.read(&mut buffer).ok()?;
 
-    String::from_utf8_lossy(&buffer[..read]).to_string()
+    Some(String::from_utf8_lossy(&buffer[..read]).to_string())
 }
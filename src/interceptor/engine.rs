@@ -0,0 +1,574 @@
+use std::time::SystemTime;
+
+use crate::{
+    config::Config,
+    event_processor::{
+        layers::{Activation, LayerStack, Layered},
+        repeat::Repeater,
+        rollover::Rollover,
+        sequence_manager::SequenceTiming,
+        sequencer::{Sequencer, SequencerKind},
+        tap_hold::{Resolved, TapHoldResolver},
+    },
+    rules::{disambiguator::Disambiguator, trie::Lookup, trigger::KeyPattern, Ruleset},
+    stuffs::{
+        key_code::KeyCode,
+        key_identifier::KeyIdentifier,
+        key_state::KeyState,
+        keyboard::{KeyTreatment, Keyboard},
+        keyboard_event::KeyboardEvent,
+    },
+};
+
+use super::{
+    rule_output::{emit_output, emit_sequence, Output},
+    sink::Action,
+};
+
+/// The processing stages a key event goes through, in order:
+/// tap-hold resolution, layers, then sequences matched against the rules.
+pub struct Engine<'a> {
+    ruleset: Ruleset,
+    sequencer_kind: SequencerKind,
+    sm: Box<dyn Sequencer<'a> + 'a>,
+    disambiguator: Disambiguator,
+    tap_hold: TapHoldResolver<'a>,
+    layers: LayerStack<'a>,
+    repeater: Repeater<'a>,
+    /// Keys whose events go to the virtual device as they are.
+    passthrough: Vec<KeyIdentifier<'a>>,
+    rollover: Rollover<'a>,
+    /// Keys down on the physical keyboards.
+    held: Vec<KeyIdentifier<'a>>,
+    /// The rule the last events completed, for `monitor`.
+    matched: Option<String>,
+    /// What the event being handled sends out.
+    actions: Vec<Action>,
+}
+
+impl<'a> Engine<'a> {
+    pub fn new(config: &Config) -> Self {
+        let sequencer_kind = *config.sequencer();
+        Self {
+            ruleset: config.rules().clone(),
+            sequencer_kind,
+            sm: sequencer_kind.build(SequenceTiming::new(
+                config.timing(),
+                config.keyboards(),
+                config.rules(),
+            )),
+            disambiguator: Disambiguator::new(config.timing().sequence_timeout_duration()),
+            tap_hold: TapHoldResolver::new(config.rules().tap_holds()),
+            layers: LayerStack::new(config.layers().clone(), config.rules().momentary_layers()),
+            repeater: Repeater::new(*config.timing().repeat()),
+            passthrough: vec![],
+            rollover: Rollover::new(),
+            held: vec![],
+            matched: None,
+            actions: vec![],
+        }
+    }
+
+    /// Runs `event` through every stage, and returns what to send out.
+    pub fn handle(&mut self, event: KeyboardEvent<'a>) -> Vec<Action> {
+        self.receive(event);
+        std::mem::take(&mut self.actions)
+    }
+
+    /// Times out the stages whose deadline passed by `now`, and returns what to send out.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Action> {
+        let resolved = self.tap_hold.expire(now);
+        self.handle_resolved(resolved);
+
+        if let Some(output) = self.disambiguator.expire(now) {
+            self.fire(&output);
+        }
+
+        let repeats = self.repeater.expire(now);
+        self.emit_repeats(&repeats);
+        std::mem::take(&mut self.actions)
+    }
+
+    /// Releases the keys held on `device`, which went away. Those releases fire no rules,
+    /// but let go of everything they hold, like passed through keys and layers.
+    /// A tap-hold key still undecided is dropped, neither tapped nor held.
+    pub fn release_device(&mut self, device: &Keyboard, now: SystemTime) -> Vec<Action> {
+        let mut held: Vec<KeyIdentifier<'a>> = self
+            .held
+            .iter()
+            .filter(|k| *k.device() == device)
+            .cloned()
+            .collect();
+
+        if let Some(undecided) = self.tap_hold.undecided().filter(|k| *k.device() == device) {
+            let undecided = undecided.clone();
+            held.retain(|k| *k != undecided);
+            self.held.retain(|k| *k != undecided);
+            let resolved = self.tap_hold.forget();
+            self.handle_resolved(resolved);
+        }
+
+        for key in held {
+            self.sm.set_emitted(true);
+            let event = KeyboardEvent::new(key, KeyState::Up, now);
+            self.receive(event);
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    /// The sequence as it stands.
+    pub fn sequence(&self) -> &str {
+        self.sm.output()
+    }
+
+    /// The rule the events since the last call completed, if any.
+    pub fn take_matched(&mut self) -> Option<String> {
+        self.matched.take()
+    }
+
+    /// Returns `true` when no keys are held in any stage.
+    pub fn is_idle(&self) -> bool {
+        self.sm.is_idle()
+            && self.tap_hold.is_idle()
+            && self.layers.is_idle()
+            && self.repeater.is_idle()
+            && self.passthrough.is_empty()
+    }
+
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.ruleset = config.rules().clone();
+        let timing = SequenceTiming::new(config.timing(), config.keyboards(), config.rules());
+        if self.sequencer_kind == *config.sequencer() {
+            self.sm.set_timing(timing);
+        } else {
+            self.sequencer_kind = *config.sequencer();
+            self.sm = self.sequencer_kind.build(timing);
+        }
+        self.disambiguator
+            .set_timeout(config.timing().sequence_timeout_duration());
+        self.tap_hold.set_keys(config.rules().tap_holds());
+        self.layers
+            .set_layers(config.layers().clone(), config.rules().momentary_layers());
+        self.repeater.set_repeat(*config.timing().repeat());
+    }
+
+    /// When the next stage times out, if any.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        [
+            self.disambiguator.deadline(),
+            self.tap_hold.deadline(),
+            self.repeater.deadline(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    fn receive(&mut self, event: KeyboardEvent<'a>) {
+        match event.value() {
+            KeyState::Down if !self.held.contains(event.key()) => {
+                self.held.push(event.key().clone());
+            }
+            KeyState::Up => self.held.retain(|k| k != event.key()),
+            _ => (),
+        }
+
+        match event.key().device().treatment(*event.key().code()) {
+            KeyTreatment::Passthrough => {
+                let value = i32::from(event.value().clone());
+                self.actions
+                    .push(Action::Keys(vec![(*event.key().code(), value)]));
+            }
+            KeyTreatment::Block => (),
+            KeyTreatment::Intercept | KeyTreatment::Swallow => {
+                let resolved = self.tap_hold.receive(event);
+                self.handle_resolved(resolved);
+            }
+        }
+    }
+
+    fn handle_resolved(&mut self, resolved: Vec<Resolved<'a>>) {
+        for resolved in resolved {
+            match resolved {
+                Resolved::Event(event) => match self.layers.receive(&event) {
+                    Layered::Pass => {
+                        self.handle_key_event(event);
+                    }
+                    Layered::Remap(code) => self.handle_remap(&event, code),
+                    Layered::Swallow => (),
+                },
+                Resolved::Emit(events) => emit_sequence(&events, &mut self.actions),
+                Resolved::Layer(name, true) => self.layers.activate(&name, Activation::Momentary),
+                Resolved::Layer(name, false) => self.layers.deactivate(&name),
+            }
+        }
+    }
+
+    /// Emits a key remapped by a layer, repeating it while held.
+    fn handle_remap(&mut self, event: &KeyboardEvent<'a>, code: KeyCode) {
+        let events = match event.value() {
+            KeyState::Down => {
+                self.repeater
+                    .press(event.key().clone(), code, *event.timestamp());
+                vec![(code, 1)]
+            }
+            KeyState::Hold => self.repeater.hold(event.key()),
+            KeyState::Up => self
+                .repeater
+                .release(event.key())
+                .unwrap_or_else(|| vec![(code, 0)]),
+        };
+        emit_sequence(&events, &mut self.actions);
+    }
+
+    /// Emits repeats of held outputs. An output that only now goes down
+    /// stands for the rule it belongs to, which must not fire again on release.
+    fn emit_repeats(&mut self, events: &[(KeyCode, i32)]) {
+        if events.iter().any(|(_, value)| *value == 1) {
+            self.sm.set_emitted(true);
+        }
+        emit_sequence(events, &mut self.actions);
+    }
+
+    /// Emits `output`, or switches layers if that's what it does.
+    fn fire(&mut self, output: &Output) {
+        match output {
+            Output::ToggleLayer(name) => self.layers.toggle(name),
+            Output::OneShotLayer(name) => self.layers.activate(name, Activation::OneShot),
+            _ => emit_output(output, &mut self.actions),
+        }
+    }
+
+    fn handle_key_event(&mut self, event: KeyboardEvent<'a>) {
+        let key = event.key().clone();
+        let value = i32::from(event.value().clone());
+        let timestamp = *event.timestamp();
+
+        self.sm.receive(event.clone());
+
+        // a held key completing a mapped rule repeats its output,
+        // unless a longer rule may follow, since the key then holds the rest of the sequence
+        match value {
+            1 => {
+                let sequence = self.sm.current_trigger();
+                if let Lookup::Exact(i) = self.ruleset.lookup(&sequence) {
+                    if let Output::Map(output) = self.ruleset.rules()[i].output() {
+                        self.repeater.watch(key.clone(), *output, timestamp);
+                    }
+                }
+            }
+            2 => {
+                let repeats = self.repeater.hold(&key);
+                self.emit_repeats(&repeats);
+            }
+            _ => {
+                if let Some(events) = self.repeater.release(&key) {
+                    emit_sequence(&events, &mut self.actions);
+                }
+            }
+        }
+
+        // FRAUD_START:
+        // a new key that can't continue the pending rule lets it fire right away
+        if value == 1 {
+            let sequence = self.sm.current_trigger();
+            if let Some(output) = self.disambiguator.press(&sequence, &self.ruleset) {
+                self.fire(&output);
+            }
+        }
+
+        // keys that can't lead to any rule go out as they are
+        let passed_through = if value == 1 && self.passes_through(&key) {
+            if !key.is_modifier() {
+                self.sm.set_emitted(true);
+            }
+            self.passthrough.push(key.clone());
+            true
+        } else if let Some(i) = self.passthrough.iter().position(|k| *k == key) {
+            if value == 0 {
+                self.passthrough.remove(i);
+            }
+            true
+        } else {
+            false
+        };
+        if passed_through {
+            self.actions.push(Action::Keys(vec![(*key.code(), value)]));
+        }
+
+        // EXPLAIN_THIS:
+        if !self.sm.emitted() && !self.sm.trigger().is_empty() {
+            let resolution =
+                self.disambiguator
+                    .complete(self.sm.trigger(), timestamp, &self.ruleset);
+
+            for output in resolution.outputs() {
+                self.fire(output);
+            }
+
+            if *resolution.consumed() {
+                self.sm.set_emitted(true);
+                self.matched = self
+                    .ruleset
+                    .find(self.sm.trigger())
+                    .map(|rule| rule.trigger().to_string());
+            }
+        }
+
+        let modifier_first = matches!(self.sm.first_key(), Some(k) if self.is_plain_modifier(k));
+        let sm = self.sm.as_mut();
+
+        // AND_THIS:
+        if !sm.emitted() && sm.is_combined() && !modifier_first {
+            self.actions.push(Action::Neovim(sm.output().to_string()));
+            sm.set_emitted(true);
+        }
+
+        // AND_THIS:
+        // keys that may still start a rule are replayed in press order once they don't
+        if !passed_through && !is_swallowed(&key) {
+            match value {
+                1 => {
+                    let modifiers = self.held_modifiers();
+                    self.rollover.defer(event, &modifiers);
+                }
+                0 if self.rollover.holds(&key) => {
+                    self.rollover.defer(event, &[]);
+                    if self.sm.emitted() {
+                        self.rollover.discard(&key, self.sm.trigger());
+                    } else {
+                        self.rollover.decide(&key);
+                    }
+                }
+                _ => (),
+            }
+        }
+        let events = self.rollover.flush(&self.held_modifiers());
+        emit_sequence(&events, &mut self.actions);
+        // FRAUD_END:
+    }
+
+    /// Modifier codes passed through and still down on the virtual device.
+    fn held_modifiers(&self) -> Vec<u16> {
+        self.passthrough
+            .iter()
+            .filter(|k| k.is_modifier())
+            .map(|k| k.code().0)
+            .collect()
+    }
+
+    /// Returns `true` for modifiers no rule starts with. Those that start one are left to
+    /// the rules, like any other key.
+    fn is_plain_modifier(&self, key: &KeyIdentifier) -> bool {
+        let pattern = KeyPattern::new(Some(key.device().alias()), *key.code());
+        key.is_modifier() && !self.ruleset.can_start(&pattern)
+    }
+
+    /// Plain modifiers always pass through. Other keys do when they can't start a rule,
+    /// the sequence they extend can't lead to one, and the keys before them passed through too.
+    fn passes_through(&self, key: &KeyIdentifier) -> bool {
+        if is_swallowed(key) {
+            return false;
+        }
+        if self.is_plain_modifier(key) {
+            return true;
+        }
+
+        let sequence = self.sm.current_trigger();
+        let pattern = KeyPattern::new(Some(key.device().alias()), *key.code());
+        let others_passed = sequence.keys().filter(|k| **k != pattern).all(|k| {
+            self.passthrough.iter().any(|p| {
+                k.device().as_deref() == Some(p.device().alias().as_str()) && k.code() == p.code()
+            })
+        });
+
+        others_passed
+            && !self.ruleset.can_start(&pattern)
+            && self.ruleset.lookup(&sequence) == Lookup::DeadEnd
+    }
+}
+
+fn is_swallowed(key: &KeyIdentifier) -> bool {
+    key.device().treatment(*key.code()) == KeyTreatment::Swallow
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod engine_module_test {
+    use crate::{config, test_utilities::mipoch, tke};
+
+    use super::*;
+
+    fn config() -> Config {
+        config::parse(
+            "test.json",
+            r#"{
+                "keyboards": [
+                    { "alias": "L1", "name": "My Left Keyboard", "path": "usb/0/0/input0" },
+                    { "alias": "R1", "name": "My Right Keyboard", "path": "usb/1/1/input0" }
+                ],
+                "rules": {
+                    "L1 CAPSLOCK": { "Map": "Esc" },
+                    "L1 D, R1 J": { "Cmd": ["firefox", []] }
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn maps_a_key_when_its_rule_completes() {
+        let config = config();
+        let L1 = config.keyboards()[0].clone();
+        let mut engine = Engine::new(&config);
+        let esc = KeyCode::from("Esc");
+
+        assert!(engine.handle(tke!(L1 CAPSLOCK Down 0)).is_empty());
+        assert_eq!(
+            engine.handle(tke!(L1 CAPSLOCK Up 100)),
+            vec![Action::Keys(vec![(esc, 1), (esc, 0)])]
+        );
+        assert_eq!(engine.take_matched(), Some("L1 CAPSLOCK".to_string()));
+        assert!(engine.is_idle());
+    }
+
+    #[test]
+    fn runs_a_command_for_a_sequence_across_keyboards() {
+        let config = config();
+        let (L1, R1) = (config.keyboards()[0].clone(), config.keyboards()[1].clone());
+        let mut engine = Engine::new(&config);
+
+        assert!(engine.handle(tke!(L1 D Down 0)).is_empty());
+        assert!(engine.handle(tke!(R1 J Down 300)).is_empty());
+        assert_eq!(
+            engine.handle(tke!(R1 J Up 400)),
+            vec![Action::Cmd("firefox".to_string(), vec![])]
+        );
+        assert!(engine.handle(tke!(L1 D Up 500)).is_empty());
+        assert_eq!(engine.deadline(), None);
+    }
+
+    #[test]
+    fn passes_through_keys_no_rule_can_use() {
+        let config = config();
+        let L1 = config.keyboards()[0].clone();
+        let mut engine = Engine::new(&config);
+        let q = KeyCode::from("Q");
+
+        assert_eq!(
+            engine.handle(tke!(L1 Q Down 0)),
+            vec![Action::Keys(vec![(q, 1)])]
+        );
+        assert_eq!(
+            engine.handle(tke!(L1 Q Up 50)),
+            vec![Action::Keys(vec![(q, 0)])]
+        );
+    }
+
+    #[test]
+    fn releases_the_keys_of_a_keyboard_that_went_away() {
+        let config = config();
+        let (L1, R1) = (config.keyboards()[0].clone(), config.keyboards()[1].clone());
+        let mut engine = Engine::new(&config);
+        let q = KeyCode::from("Q");
+
+        engine.handle(tke!(L1 Q Down 0));
+        engine.handle(tke!(R1 Q Down 50));
+        assert_eq!(
+            engine.release_device(&L1, mipoch(100)),
+            vec![Action::Keys(vec![(q, 0)])]
+        );
+        assert_eq!(
+            engine.handle(tke!(R1 Q Up 150)),
+            vec![Action::Keys(vec![(q, 0)])]
+        );
+        assert!(engine.is_idle());
+    }
+
+    #[test]
+    fn drops_undecided_tap_holds_of_a_keyboard_that_went_away() {
+        let config = config::parse(
+            "test.json",
+            r#"{
+                "keyboards": [
+                    { "alias": "L1", "name": "My Left Keyboard", "path": "usb/0/0/input0" }
+                ],
+                "rules": {
+                    "L1 CAPSLOCK": { "TapHold": { "tap": "Esc", "hold": "LeftCtrl" } }
+                }
+            }"#,
+        )
+        .unwrap();
+        let L1 = config.keyboards()[0].clone();
+        let mut engine = Engine::new(&config);
+
+        assert!(engine.handle(tke!(L1 CAPSLOCK Down 0)).is_empty());
+        assert!(engine.release_device(&L1, mipoch(50)).is_empty());
+        assert_eq!(engine.deadline(), None);
+        assert!(engine.is_idle());
+    }
+
+    #[test]
+    fn modifiers_starting_a_rule_go_to_the_rules() {
+        let config = config::parse(
+            "test.json",
+            r#"{
+                "keyboards": [
+                    { "alias": "R1", "name": "My Right Keyboard", "path": "usb/1/1/input0" }
+                ],
+                "rules": {
+                    "R1 RIGHTALT": { "Map": "F1" }
+                }
+            }"#,
+        )
+        .unwrap();
+        let R1 = config.keyboards()[0].clone();
+        let mut engine = Engine::new(&config);
+        let f1 = KeyCode::from("F1");
+
+        assert!(engine.handle(tke!(R1 RIGHTALT Down 0)).is_empty());
+        assert_eq!(
+            engine.handle(tke!(R1 RIGHTALT Up 100)),
+            vec![Action::Keys(vec![(f1, 1), (f1, 0)])]
+        );
+
+        assert!(engine.handle(tke!(R1 RIGHTALT Down 1000)).is_empty());
+        assert!(engine.handle(tke!(R1 Q Down 1300)).is_empty());
+        assert_eq!(
+            engine.handle(tke!(R1 Q Up 1400)),
+            vec![Action::Neovim("R1 RIGHTALT, R1 Q".to_string())]
+        );
+        assert!(engine.handle(tke!(R1 RIGHTALT Up 1500)).is_empty());
+        assert!(engine.is_idle());
+    }
+
+    #[test]
+    fn keys_held_before_a_longer_rule_dont_repeat() {
+        let config = config::parse(
+            "test.json",
+            r#"{
+                "keyboards": [
+                    { "alias": "L1", "name": "My Left Keyboard", "path": "usb/0/0/input0" },
+                    { "alias": "R1", "name": "My Right Keyboard", "path": "usb/1/1/input0" }
+                ],
+                "rules": {
+                    "L1 CAPSLOCK": { "Map": "Esc" },
+                    "L1 CAPSLOCK, R1 H": { "Map": "Left" }
+                }
+            }"#,
+        )
+        .unwrap();
+        let (L1, R1) = (config.keyboards()[0].clone(), config.keyboards()[1].clone());
+        let mut engine = Engine::new(&config);
+        let left = KeyCode::from("Left");
+
+        let mut actions = engine.handle(tke!(L1 CAPSLOCK Down 0));
+        actions.extend(engine.handle(tke!(L1 CAPSLOCK Hold 500)));
+        actions.extend(engine.handle(tke!(L1 CAPSLOCK Hold 530)));
+        actions.extend(engine.handle(tke!(R1 H Down 700)));
+        actions.extend(engine.handle(tke!(R1 H Up 800)));
+        actions.extend(engine.handle(tke!(L1 CAPSLOCK Up 900)));
+
+        assert_eq!(actions, vec![Action::Keys(vec![(left, 1), (left, 0)])]);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    path::PathBuf,
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+};
+
+use inotify::{Inotify, WatchMask};
+
+use crate::{
+    devices::input::{self, DeviceError},
+    stuffs::keyboard::Keyboard,
+};
+
+use super::{intercept, StartError, TransmitSignal};
+
+const INPUT_DIR: &str = "/dev/input";
+
+/// Aliases of the keyboards being read, with their device node.
+pub type Attached = Arc<Mutex<Vec<(String, PathBuf)>>>;
+
+/// Grabs the configured keyboards, then watches `/dev/input` for the ones
+/// that go missing, so they are grabbed again when they come back.
+/// A keyboard that can't be found or grabbed at startup is an error, unless told to `wait`
+/// for it. One matching several devices always is. Later on, a device that can't
+/// be grabbed is left alone, and tried again when input devices next change.
+/// With `grab` off, devices are only read, and keep working as usual.
+pub fn watch(
+    keyboards: Vec<Keyboard>,
+    tx: Sender<TransmitSignal>,
+    grab: bool,
+    wait: bool,
+) -> Result<(), StartError> {
+    let attached: Attached = Arc::default();
+
+    for (keyboard, err) in attach_missing(&keyboards, &tx, &attached, grab) {
+        match err {
+            DeviceError::NotFound(..) | DeviceError::Grab(..) if wait => {
+                warn!("Waiting for {}: {err}", keyboard.alias());
+            }
+            _ => return Err(StartError::Device(keyboard.alias().clone(), err)),
+        }
+    }
+
+    let mut inotify = match Inotify::init().and_then(|inotify| {
+        let mask = WatchMask::CREATE | WatchMask::DELETE | WatchMask::ATTRIB;
+        inotify.watches().add(INPUT_DIR, mask)?;
+        Ok(inotify)
+    }) {
+        Ok(inotify) => inotify,
+        Err(err) => {
+            error!("Can't watch {INPUT_DIR}, keyboards that go away won't be grabbed again: {err}");
+            return Ok(());
+        }
+    };
+
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        let mut reported: Vec<String> = vec![];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(err) => {
+                    error!("Stopped watching {INPUT_DIR}: {err}");
+                    return;
+                }
+            };
+            // event nodes added, removed, or given their permissions once added
+            let changed = events.into_iter().any(|e| {
+                e.name
+                    .is_some_and(|n| n.to_string_lossy().starts_with("event"))
+            });
+            if !changed {
+                continue;
+            }
+
+            let failures = attach_missing(&keyboards, &tx, &attached, grab);
+            // report an ambiguous match or a failed grab once, not on every change
+            let messages: Vec<String> = failures
+                .iter()
+                .filter(|(_, err)| !matches!(err, DeviceError::NotFound(..)))
+                .map(|(keyboard, err)| format!("Can't grab {}: {err}", keyboard.alias()))
+                .collect();
+            for message in &messages {
+                if !reported.contains(message) {
+                    error!("{message}");
+                }
+            }
+            reported = messages;
+        }
+    });
+
+    Ok(())
+}
+
+/// Grabs every keyboard that isn't attached and can be found,
+/// and returns the ones that can't with why.
+fn attach_missing<'k>(
+    keyboards: &'k [Keyboard],
+    tx: &Sender<TransmitSignal>,
+    attached: &Attached,
+    grab: bool,
+) -> Vec<(&'k Keyboard, DeviceError)> {
+    let is_attached = |keyboard: &Keyboard| {
+        let attached = attached.lock().unwrap();
+        attached.iter().any(|(alias, _)| alias == keyboard.alias())
+    };
+    if keyboards.iter().all(is_attached) {
+        return vec![];
+    }
+
+    // devices already grabbed can't be picked again
+    let mut devices: Vec<_> = {
+        let attached = attached.lock().unwrap();
+        evdev::enumerate()
+            .filter(|(node, _)| attached.iter().all(|(_, n)| n != node))
+            .collect()
+    };
+
+    let mut failures = vec![];
+    for keyboard in keyboards.iter().filter(|k| !is_attached(k)) {
+        match input::find_in(&mut devices, &keyboard.device_matcher()) {
+            Ok((node, device)) => {
+                let result = intercept(
+                    tx.clone(),
+                    keyboard,
+                    node.clone(),
+                    device,
+                    attached.clone(),
+                    grab,
+                );
+                if let Err(err) = result {
+                    failures.push((keyboard, DeviceError::Grab(node, err)));
+                }
+            }
+            Err(err) => failures.push((keyboard, err)),
+        }
+    }
+    failures
+}
@@ -1,215 +1,510 @@
-mod rule_output;
+pub mod engine;
+pub mod hotplug;
+pub mod monitor;
+pub mod rule_output;
+pub mod sink;
+pub mod source;
 
 use std::{
-    collections::HashMap,
-    sync::mpsc::{self, Sender},
+    fmt::Display,
+    fs::File,
+    io::{self, LineWriter, Write},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
     thread,
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
+use evdev::Device;
+
 use crate::{
-    devices::{self, input::EventKindCheck, output::virtual_event},
-    event_processor::sequence_manager::SequenceManager,
+    cli::exit,
+    config::{self, Config, ConfigError},
+    devices::{self, input::DeviceError},
     stuffs::{key_identifier::KeyIdentifier, keyboard::Keyboard, keyboard_event::KeyboardEvent},
 };
 
-use self::rule_output::{emit_cmd, emit_mapped_key, emit_sequence, send_signal_to_neovim, Output};
+use self::engine::Engine;
+use self::hotplug::Attached;
+use self::monitor::Report;
+use self::sink::{Action, Logger, OutputSink, Uinput};
+use self::source::{Evdev, Recording};
 
 pub enum TransmitSignal {
     Key(String, u16, i32, SystemTime),
     NeovimTCPPort(String),
+    Reload(Config),
+    /// The keyboard with this alias went away.
+    Detached(String),
 }
 
-// for development purposes only
-fn mock_keyboard_devices() -> Vec<Keyboard> {
-    vec![
-        Keyboard::new("L1", "Left Keyboard", "usb-0000:00:1d.0-1.5.1.4/input0"),
-        Keyboard::new("R1", "Right Keyboard", "usb-0000:00:1d.0-1.5.2/input0"),
-    ]
-}
-
-// for development purposes only
-fn create_mock_ruleset() -> HashMap<&'static str, Output> {
-    HashMap::from([
-        // Escape Key
-        ("L1 CAPSLOCK", Output::Map("Esc")),
-        // Arrow Keys
-        ("L1 CAPSLOCK, R1 H", Output::Map("Left")),
-        ("L1 CAPSLOCK, R1 J", Output::Map("Down")),
-        ("L1 CAPSLOCK, R1 K", Output::Map("Up")),
-        ("L1 CAPSLOCK, R1 L", Output::Map("Right")),
-        // Playback Keys
-        ("L1 H, R1 J", Output::Map("VolumeDown")),
-        ("L1 H, R1 K", Output::Map("VolumeUp")),
-        ("L1 H, R1 P", Output::Map("PreviousSong")),
-        ("L1 H, R1 N", Output::Map("NextSong")),
-        ("L1 H, R1 I", Output::Map("PlayPause")),
-        // Cmd Test
-        // ("L1 E, R1 K", Output::Cmd("kitty", vec![])),
-        // ("L1 E, L1 F", Output::Cmd("firefox", vec![])),
-        // ("L1 E, R1 K, R1 J", Output::Cmd("gedit", vec![])),
-        // Remap Right Alt to <C-F1>
-        (
-            "R1 RIGHTALT",
-            Output::Sequence(vec![("LeftCtrl", 1), ("F1", 1), ("F1", 0), ("LeftCtrl", 0)]),
-        ),
-        // Browser links
-        (
-            "R1 B, L1 1",
-            Output::Cmd("xdg-open", vec!["https://1337x.to/"]),
-        ),
-        (
-            "R1 B, L1 F",
-            Output::Cmd("xdg-open", vec!["https://youtube.com/"]),
-        ),
-        (
-            "R1 B, L1 R",
-            Output::Cmd(
-                "xdg-open",
-                vec!["https://discord.com/channels/701530051140780102/813520701281271928"],
-            ),
-        ),
-    ])
+#[derive(Debug)]
+pub enum StartError {
+    Config(ConfigError),
+    Device(String, DeviceError),
+    /// The uinput device keys are sent through can't be created.
+    Output(io::Error),
+    Recording(String),
 }
 
-pub fn start() {
-    // Development Variables
-    let keyboard_devices = mock_keyboard_devices();
-    let ruleset = create_mock_ruleset();
+impl StartError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            StartError::Config(_) => exit::CONFIG,
+            StartError::Device(..) => exit::UNAVAILABLE,
+            StartError::Output(_) => exit::CANT_CREATE,
+            StartError::Recording(_) => exit::IO_ERR,
+        }
+    }
+}
+
+impl Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartError::Config(err) => write!(f, "{err}"),
+            StartError::Device(alias, err) => write!(f, "Keyboard \"{alias}\": {err}"),
+            StartError::Output(err) => write!(f, "Failed to create the virtual keyboard: {err}"),
+            StartError::Recording(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<ConfigError> for StartError {
+    fn from(err: ConfigError) -> Self {
+        StartError::Config(err)
+    }
+}
+
+/// How `start` runs the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Run,
+    /// Prints what the engine sees and does with every event.
+    Monitor,
+}
+
+/// What time it is for the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Clock {
+    /// The system clock, for keyboards being typed on.
+    System,
+    /// The time of the last event, for replays. Deadlines pass as the events reach them,
+    /// so a replay comes out the same however fast it goes.
+    Events,
+}
+
+/// How long a replay goes on after its last event, for what's pending to time out.
+const REPLAY_SETTLE: Duration = Duration::from_secs(1);
+
+/// Runs the engine on the keyboards in the config at `config_path`.
+/// A dry run reads the keyboards without grabbing them, and sends nothing out.
+/// With `wait`, keyboards missing at startup are waited for instead of failing.
+/// Key events can be recorded to `record`, to be replayed later.
+pub fn start(
+    config_path: &str,
+    mode: Mode,
+    dry_run: bool,
+    wait: bool,
+    record: Option<&str>,
+) -> Result<(), StartError> {
+    let config = config::load(config_path)?;
 
     // Message Channels
     let (tx, rx) = mpsc::channel();
+    let rx = match record {
+        Some(path) => record_keys(rx, path)?,
+        None => rx,
+    };
 
-    for keyboard in &keyboard_devices {
-        intercept(tx.clone(), keyboard);
-    }
+    hotplug::watch(config.keyboards().clone(), tx.clone(), !dry_run, wait)?;
 
     // HTTP server
-    let mut nvim_port = String::new();
-
+    config::watcher::watch(config_path, tx.clone());
     crate::http_server::start_server(tx);
 
     // Interception
-    let mut virtual_device = devices::output::new().unwrap();
-    let mut sm = SequenceManager::new();
+    // a monitor shows what goes out already, a dry run alone logs it
+    let mut sinks: Vec<Box<dyn OutputSink>> = match (mode, dry_run) {
+        (_, false) => vec![Box::new(Uinput::new(
+            devices::output::new().map_err(StartError::Output)?,
+        ))],
+        (Mode::Monitor, true) => vec![],
+        (Mode::Run, true) => vec![Box::new(Logger)],
+    };
+    let mut sinks: Vec<&mut dyn OutputSink> = sinks
+        .iter_mut()
+        .map(|s| -> &mut dyn OutputSink { s.as_mut() })
+        .collect();
+    run(config, config_path, &rx, mode, Clock::System, &mut sinks);
 
-    for signal in rx {
-        match signal {
-            TransmitSignal::NeovimTCPPort(port) => {
-                nvim_port = port;
+    Ok(())
+}
+
+/// Feeds the key events recorded at `path` through the engine, printing what comes of them
+/// like `monitor` does. Nothing is sent out. `speed` scales the time between events,
+/// 0 replays them without waiting.
+pub fn replay(config_path: &str, path: &str, speed: f64) -> Result<(), StartError> {
+    let config = config::load(config_path)?;
+    let mut source = Recording::open(path)
+        .map_err(StartError::Recording)?
+        .with_speed(speed);
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || source::pump(&mut source, &tx));
+    run(
+        config,
+        config_path,
+        &rx,
+        Mode::Monitor,
+        Clock::Events,
+        &mut [],
+    );
+
+    Ok(())
+}
+
+/// Writes the key events coming down `rx` to the file at `path`, and passes every signal on.
+fn record_keys(
+    rx: Receiver<TransmitSignal>,
+    path: &str,
+) -> Result<Receiver<TransmitSignal>, StartError> {
+    let file = File::create(path)
+        .map_err(|err| StartError::Recording(format!("Failed to create {path}: {err}")))?;
+    let mut file = LineWriter::new(file);
+    info!("Recording key events to {path}");
+
+    let (tx, recorded) = mpsc::channel();
+    thread::spawn(move || {
+        for signal in rx {
+            if let TransmitSignal::Key(alias, code, value, timestamp) = &signal {
+                let line = source::format_key(&(alias.clone(), *code, *value, *timestamp));
+                if let Err(err) = writeln!(file, "{line}") {
+                    error!("Failed to record key events: {err}");
+                }
             }
-            TransmitSignal::Key(device_alias, code, value, timestamp) => {
-                if let Some(device) = keyboard_devices.iter().find(|d| *d.alias() == device_alias) {
-                    let key = KeyIdentifier::new(device, code);
-                    let event = KeyboardEvent::new(key, value, timestamp);
+            if tx.send(signal).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(recorded)
+}
 
-                    sm.receive(event);
-
-                    // FRAUD_START:
-                    let get_rule_from_ruleset = ruleset.get(sm.output().as_str());
-                    // EXPLAIN_THIS:
-                    if let Some(rule) = get_rule_from_ruleset {
-                        match rule {
-                            Output::Map(mapping) => {
-                                emit_mapped_key(mapping, &sm, &mut virtual_device);
-                            }
-                            Output::Cmd(cmd, args) => emit_cmd(cmd, args, &sm),
-                            Output::Sequence(sequence) => {
-                                emit_sequence(sequence, &mut virtual_device);
-                            }
-                        }
+/// Feeds the signals from `rx` through the engine, and what comes out to `sinks`,
+/// until every sender is gone.
+fn run(
+    mut config: Config,
+    config_path: &str,
+    rx: &Receiver<TransmitSignal>,
+    mode: Mode,
+    clock: Clock,
+    sinks: &mut [&mut dyn OutputSink],
+) {
+    let keyboard_devices = config.keyboards().clone();
+    let mut pending_config: Option<Config> = None;
+    let mut engine = Engine::new(&config);
+    // the time of the last event
+    let mut last = SystemTime::UNIX_EPOCH;
 
-                        sm.set_emitted(true);
-                    }
+    loop {
+        // Swap only while no keys are held, so a sequence started under
+        // the old ruleset is also released under it.
+        if engine.is_idle() {
+            if let Some(new_config) = pending_config.take() {
+                config = new_config;
+                engine.reconfigure(&config);
+                info!("Reloaded {} rules from {config_path}", config.rules().len());
+            }
+        }
+
+        let received = match engine.deadline().filter(|_| clock == Clock::System) {
+            Some(deadline) => {
+                let timeout = deadline
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                rx.recv_timeout(timeout)
+            }
+            None => rx.recv().map_err(RecvTimeoutError::from),
+        };
 
-                    // AND_THIS:
-                    if !sm.emitted() && sm.is_combined() {
-                        let modifiers: Vec<u16> = vec![14, 29, 42, 54, 56, 97, 125, 126];
+        let signal = match received {
+            Ok(signal) => signal,
+            Err(RecvTimeoutError::Timeout) => {
+                expire(&mut engine, SystemTime::now(), mode, sinks);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                if clock == Clock::Events {
+                    expire_until(&mut engine, last + REPLAY_SETTLE, mode, sinks);
+                }
+                break;
+            }
+        };
 
-                        if !modifiers.contains(&sm.first_code()) {
-                            send_signal_to_neovim(&nvim_port, sm.output());
-                            sm.set_emitted(true);
+        match signal {
+            TransmitSignal::NeovimTCPPort(port) => {
+                for sink in sinks.iter_mut() {
+                    sink.set_nvim_port(&port);
+                }
+            }
+            TransmitSignal::Reload(new_config) => {
+                // the devices were matched and grabbed at start, against the old list
+                if *new_config.keyboards() != keyboard_devices {
+                    warn!("Keyboard changes in {config_path} need a restart, keeping the current config.");
+                    continue;
+                }
+                pending_config = Some(new_config);
+            }
+            TransmitSignal::Detached(device_alias) => {
+                if let Some(device) = keyboard_devices.iter().find(|d| *d.alias() == device_alias) {
+                    warn!("Lost {device_alias}, waiting for it to come back");
+                    let actions = engine.release_device(device, SystemTime::now());
+                    if mode == Mode::Monitor {
+                        let report =
+                            report(format!("(lost {device_alias})"), &mut engine, &actions);
+                        if !report.is_empty() {
+                            println!("{report}");
                         }
                     }
+                    send(sinks, &actions);
+                }
+            }
+            TransmitSignal::Key(device_alias, code, value, timestamp) => {
+                if let Some(device) = keyboard_devices.iter().find(|d| *d.alias() == device_alias) {
+                    if clock == Clock::Events {
+                        expire_until(&mut engine, timestamp, mode, sinks);
+                        last = timestamp;
+                    }
 
-                    // AND_THIS:
-                    if !sm.emitted() {
-                        emit_only_on_key_up_experiment(value, code, &mut virtual_device, &sm);
+                    let key = KeyIdentifier::new(device, code);
+                    let event = KeyboardEvent::new(key, value, timestamp);
+                    let shown = event.to_string();
+
+                    let actions = engine.handle(event);
+                    if mode == Mode::Monitor {
+                        println!("{}", report(shown, &mut engine, &actions));
                     }
-                    // FRAUD_END:
+                    send(sinks, &actions);
                 }
             }
         }
     }
 }
 
-fn emit_only_on_key_up_experiment(
-    value: i32,
-    code: u16,
-    virtual_device: &mut evdev::uinput::VirtualDevice,
-    sm: &SequenceManager,
-) {
-    let modifiers: Vec<u16> = vec![14, 29, 42, 54, 56, 97, 125, 126];
-    let ignore_list: Vec<u16> = vec![58];
-
-    if ignore_list.contains(&code) {
-        return;
+/// Times out what is due by `now`.
+fn expire(engine: &mut Engine, now: SystemTime, mode: Mode, sinks: &mut [&mut dyn OutputSink]) {
+    let actions = engine.expire(now);
+    if mode == Mode::Monitor {
+        let report = report("(timeout)".to_string(), engine, &actions);
+        if !report.is_empty() {
+            println!("{report}");
+        }
     }
+    send(sinks, &actions);
+}
 
-    if modifiers.contains(&code) {
-        let event = virtual_event(code, value);
-        virtual_device.emit(&[event]).unwrap();
+/// Times out one deadline after the other, up to `now`.
+fn expire_until(
+    engine: &mut Engine,
+    now: SystemTime,
+    mode: Mode,
+    sinks: &mut [&mut dyn OutputSink],
+) {
+    while let Some(deadline) = engine.deadline().filter(|d| *d <= now) {
+        expire(engine, deadline, mode, sinks);
     }
+}
 
-    if !modifiers.contains(&code) && value == 0 && !sm.emitted() {
-        // handle down events
-        let mut events = vec![];
-        for modifier_code in sm.modifiers() {
-            events.push(virtual_event(*modifier_code, 1));
+fn send(sinks: &mut [&mut dyn OutputSink], actions: &[Action]) {
+    for action in actions {
+        for sink in sinks.iter_mut() {
+            sink.send(action);
         }
-        events.push(virtual_event(code, 1));
+    }
+}
 
-        // handle up events
-        let mut up_events = vec![];
-        for modifier_code in sm.modifiers() {
-            up_events.push(virtual_event(*modifier_code, 0));
-        }
-        up_events.push(virtual_event(code, 0));
+/// What came of the last event, as `monitor` prints it.
+fn report(event: String, engine: &mut Engine, actions: &[Action]) -> Report {
+    let rule = engine.take_matched();
+    Report::new(event, engine.sequence(), rule, actions.to_vec())
+}
+
+/// Reads the `device` at `node` for `keyboard`, grabbing it first unless `grab` is off.
+/// A device that can't be grabbed is dropped, so its keys aren't read on top of going out as usual.
+fn intercept(
+    tx: Sender<TransmitSignal>,
+    keyboard: &Keyboard,
+    node: PathBuf,
+    mut device: Device,
+    attached: Attached,
+    grab: bool,
+) -> io::Result<()> {
+    let alias = keyboard.alias().clone();
+    let path = device.physical_path().unwrap_or_default().to_string();
 
-        // append and emit
-        events.append(&mut up_events);
-        virtual_device.emit(&events).unwrap();
+    if grab {
+        device.grab()?;
+        info!("Grabbed {alias} {path}");
+    } else {
+        info!("Reading {alias} {path} without grabbing it");
     }
+    attached.lock().unwrap().push((alias.clone(), node));
+
+    let mut source = Evdev::new(&alias, device);
+    thread::spawn(move || {
+        source::pump(&mut source, &tx);
+        // the device was unplugged
+        attached.lock().unwrap().retain(|(a, _)| *a != alias);
+        tx.send(TransmitSignal::Detached(alias)).ok();
+    });
+    Ok(())
 }
 
-fn intercept(tx: Sender<TransmitSignal>, device: &Keyboard) {
-    let alias = device.alias().clone();
-    let path = device.path();
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod interceptor_module_test {
+    use crate::{stuffs::key_code::KeyCode, test_utilities::mipoch};
 
-    let mut d = devices::input::from_path(path);
-    match d.grab() {
-        Ok(_) => println!("Grabbed {alias} {path} SUCCESSFULLY"),
-        Err(err) => {
-            println!("FAILED TO GRAB {alias} {path},\n{err},\n------------------",);
-        }
+    use super::{sink::Recorder, source::Channel, *};
+
+    #[test]
+    fn runs_key_events_from_any_source() {
+        let config = config::parse(
+            "test.json",
+            r#"{
+                "keyboards": [
+                    { "alias": "L1", "name": "My Left Keyboard", "path": "usb/0/0/input0" }
+                ],
+                "rules": { "L1 CAPSLOCK": { "Map": "Esc" } }
+            }"#,
+        )
+        .unwrap();
+        let (keys, mut source) = Channel::new();
+        let (tx, rx) = mpsc::channel();
+
+        keys.send(("L1".to_string(), 58, 1, mipoch(0))).unwrap();
+        keys.send(("L1".to_string(), 58, 0, mipoch(100))).unwrap();
+        drop(keys);
+        thread::spawn(move || source::pump(&mut source, &tx));
+
+        let mut recorder = Recorder::default();
+        run(
+            config,
+            "test.json",
+            &rx,
+            Mode::Run,
+            Clock::System,
+            &mut [&mut recorder],
+        );
+
+        let esc = KeyCode::from("Esc");
+        assert_eq!(
+            recorder.take(),
+            vec![Action::Keys(vec![(esc, 1), (esc, 0)])]
+        );
     }
 
-    thread::spawn(move || loop {
-        match d.fetch_events() {
-            Err(err) => println!("Error fetching events. {err}"),
-            Ok(events) => {
-                for ev in events {
-                    if ev.is_type_key() {
-                        tx.send(TransmitSignal::Key(
-                            alias.to_string(),
-                            ev.code(),
-                            ev.value(),
-                            ev.timestamp(),
-                        ))
-                        .unwrap();
-                    }
+    #[test]
+    fn reloads_that_change_the_keyboards_are_rejected() {
+        let parse = |keyboard: &str, rule: &str| {
+            config::parse(
+                "test.json",
+                &format!(
+                    r#"{{
+                        "keyboards": [
+                            {{ "alias": "L1", "name": "{keyboard}", "path": "usb/0/0/input0" }}
+                        ],
+                        "rules": {{ "L1 CAPSLOCK": {{ "Map": "{rule}" }} }}
+                    }}"#
+                ),
+            )
+            .unwrap()
+        };
+        let (keys, mut source) = Channel::new();
+        let (tx, rx) = mpsc::channel();
+
+        tx.send(TransmitSignal::Reload(parse("My Other Keyboard", "Tab")))
+            .unwrap();
+        keys.send(("L1".to_string(), 58, 1, mipoch(0))).unwrap();
+        keys.send(("L1".to_string(), 58, 0, mipoch(100))).unwrap();
+        drop(keys);
+        thread::spawn(move || source::pump(&mut source, &tx));
+
+        let mut recorder = Recorder::default();
+        run(
+            parse("My Left Keyboard", "Esc"),
+            "test.json",
+            &rx,
+            Mode::Run,
+            Clock::System,
+            &mut [&mut recorder],
+        );
+
+        let esc = KeyCode::from("Esc");
+        assert_eq!(
+            recorder.take(),
+            vec![Action::Keys(vec![(esc, 1), (esc, 0)])]
+        );
+    }
+
+    #[test]
+    fn keyboards_that_cant_be_read_exit_unavailable() {
+        let not_found = StartError::Device(
+            "L1".to_string(),
+            DeviceError::NotFound("path \"usb/0/0/input0\"".to_string(), vec![]),
+        );
+        let busy = StartError::Device(
+            "L1".to_string(),
+            DeviceError::Grab(
+                PathBuf::from("/dev/input/event3"),
+                io::Error::from_raw_os_error(16),
+            ),
+        );
+        assert_eq!(not_found.exit_code(), exit::UNAVAILABLE);
+        assert_eq!(busy.exit_code(), exit::UNAVAILABLE);
+        assert!(busy
+            .to_string()
+            .starts_with("Keyboard \"L1\": Failed to grab /dev/input/event3"));
+    }
+
+    #[test]
+    fn replays_on_the_clock_of_the_recording() {
+        let config = config::parse(
+            "test.json",
+            r#"{
+                "keyboards": [
+                    { "alias": "L1", "name": "My Left Keyboard", "path": "usb/0/0/input0" },
+                    { "alias": "R1", "name": "My Right Keyboard", "path": "usb/1/1/input0" }
+                ],
+                "rules": {
+                    "L1 CAPSLOCK": { "Map": "Esc" },
+                    "L1 CAPSLOCK, R1 H": { "Map": "Left" }
                 }
-            }
-        }
-    });
+            }"#,
+        )
+        .unwrap();
+        let recording = "L1 58 1 0\nL1 58 0 100000\nL1 58 1 5000000\nR1 35 1 5300000\nR1 35 0 5400000\nL1 58 0 5500000\n";
+        let mut source = Recording::new(source::parse_recording(recording).unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || source::pump(&mut source, &tx));
+
+        let mut recorder = Recorder::default();
+        run(
+            config,
+            "test.json",
+            &rx,
+            Mode::Run,
+            Clock::Events,
+            &mut [&mut recorder],
+        );
+
+        let (esc, left) = (KeyCode::from("Esc"), KeyCode::from("Left"));
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Action::Keys(vec![(esc, 1), (esc, 0)]),
+                Action::Keys(vec![(left, 1), (left, 0)])
+            ]
+        );
+    }
 }
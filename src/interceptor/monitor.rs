@@ -0,0 +1,76 @@
+use std::fmt::Display;
+
+use super::sink::Action;
+
+/// What the engine did with one event, as `monitor` prints it.
+pub struct Report {
+    event: String,
+    sequence: String,
+    rule: Option<String>,
+    emitted: Vec<Action>,
+}
+
+impl Report {
+    pub fn new(event: String, sequence: &str, rule: Option<String>, emitted: Vec<Action>) -> Self {
+        Self {
+            event,
+            sequence: sequence.to_string(),
+            rule,
+            emitted,
+        }
+    }
+
+    /// Returns `true` when the engine did nothing visible.
+    pub fn is_empty(&self) -> bool {
+        self.sequence.is_empty() && self.rule.is_none() && self.emitted.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<20}", self.event)?;
+        if !self.sequence.is_empty() {
+            write!(f, "  sequence \"{}\"", self.sequence)?;
+        }
+        if let Some(rule) = &self.rule {
+            write!(f, "  rule \"{rule}\"")?;
+        }
+        if !self.emitted.is_empty() {
+            let emitted: Vec<String> = self.emitted.iter().map(Action::to_string).collect();
+            write!(f, "  emitted {}", emitted.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod monitor_module_test {
+    use crate::{
+        stuffs::{key_code::KeyCode, keyboard::Keyboard, keyboard_event::KeyboardEvent},
+        tke,
+    };
+
+    use super::*;
+
+    #[test]
+    fn shows_the_event_and_what_came_of_it() {
+        let L1 = Keyboard::new("L1", "My Left Keyboard", "usb/0/0/input0");
+        let esc = KeyCode::from("Esc");
+
+        let report = Report::new(tke!(L1 CAPSLOCK Down 0).to_string(), "", None, vec![]);
+        assert!(report.is_empty());
+        assert_eq!(report.to_string().trim_end(), "L1 CAPSLOCK");
+
+        let report = Report::new(
+            tke!(L1 CAPSLOCK Up 100).to_string(),
+            "L1 CAPSLOCK",
+            Some("L1 CAPSLOCK".to_string()),
+            vec![Action::Keys(vec![(esc, 1), (esc, 0)])],
+        );
+        assert_eq!(
+            report.to_string(),
+            "!L1 CAPSLOCK          sequence \"L1 CAPSLOCK\"  rule \"L1 CAPSLOCK\"  emitted ESC Down, ESC Up"
+        );
+    }
+}
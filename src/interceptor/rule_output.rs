@@ -1,39 +1,144 @@
-use std::{io::Read, process::Command};
+use std::{fmt, io::Read};
 
-use evdev::uinput::VirtualDevice;
-
-use crate::{
-    devices::output::virtual_event, event_processor::sequence_manager::SequenceManager,
-    stuffs::key_code::KeyCode,
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
 };
 
+use crate::stuffs::key_code::KeyCode;
+
+use super::sink::Action;
+
+/// What a rule produces once its trigger matches.
+/// Key names are resolved into `KeyCode`s when the rule is deserialized,
+/// so invalid names are rejected at load time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Output {
-    Map(&'static str),
-    Cmd(&'static str, Vec<&'static str>),
-    Sequence(Vec<(&'static str, i32)>),
+    Map(KeyCode),
+    Cmd(String, Vec<String>),
+    Sequence(Vec<(KeyCode, i32)>),
+    TapHold(TapHold),
+    /// Activates a layer while the trigger key is held. Single key triggers only.
+    MomentaryLayer(String),
+    ToggleLayer(String),
+    /// Activates a layer for the next key press.
+    OneShotLayer(String),
+}
+
+impl Output {
+    /// Outputs resolved per key, before sequences are matched.
+    pub fn is_key_level(&self) -> bool {
+        matches!(self, Output::TapHold(_) | Output::MomentaryLayer(_))
+    }
+
+    /// Name of the layer this output refers to, if any.
+    pub fn layer(&self) -> Option<&str> {
+        match self {
+            Output::MomentaryLayer(name)
+            | Output::ToggleLayer(name)
+            | Output::OneShotLayer(name) => Some(name),
+            Output::TapHold(tap_hold) => match tap_hold.hold() {
+                Hold::Layer { layer } => Some(layer),
+                Hold::Key(_) => None,
+            },
+            Output::Map(_) | Output::Cmd(..) | Output::Sequence(_) => None,
+        }
+    }
+}
+
+/// A dual-role key: sends `tap` when tapped, and holds `hold` while held.
+/// Only valid for single key triggers, which are then resolved ahead of sequences.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Getters)]
+#[serde(deny_unknown_fields)]
+pub struct TapHold {
+    #[getset(get = "pub")]
+    tap: KeyCode,
+
+    #[getset(get = "pub")]
+    hold: Hold,
+
+    /// How long, in ms, the key must be held before it counts as held.
+    #[serde(default = "default_tapping_term")]
+    #[getset(get = "pub")]
+    tapping_term: u64,
+
+    /// Counts as held if another key is pressed and released within the tapping term.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    permissive_hold: bool,
+
+    /// Counts as held as soon as another key is pressed within the tapping term.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    hold_on_other_key_press: bool,
+}
+
+fn default_tapping_term() -> u64 {
+    200
+}
+
+/// What a `TapHold` key holds: either a key name, or `{ "layer": name }`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Hold {
+    Key(KeyCode),
+    Layer { layer: String },
 }
 
-pub fn emit_mapped_key(key: &str, sm: &SequenceManager, virtual_device: &mut VirtualDevice) {
-    let code = KeyCode::from(key).0;
-    if !sm.emitted() {
-        virtual_device
-            .emit(&[virtual_event(code, 1), virtual_event(code, 0)])
-            .unwrap();
+impl<'de> Deserialize<'de> for Hold {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HoldVisitor;
+
+        impl<'de> Visitor<'de> for HoldVisitor {
+            type Value = Hold;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a key name or {{ \"layer\": name }}")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map(Hold::Key).map_err(E::custom)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                match map.next_entry::<String, String>()? {
+                    Some((key, layer)) if key == "layer" && map.next_key::<String>()?.is_none() => {
+                        Ok(Hold::Layer { layer })
+                    }
+                    _ => Err(de::Error::custom("expected { \"layer\": name }")),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(HoldVisitor)
     }
 }
 
-pub fn emit_cmd(cmd: &str, args: &[&str], sm: &SequenceManager) {
-    if !sm.emitted() {
-        Command::new(cmd).args(args).spawn().ok();
+pub fn emit_output(output: &Output, actions: &mut Vec<Action>) {
+    match output {
+        Output::Map(key) => emit_mapped_key(*key, actions),
+        Output::Cmd(cmd, args) => emit_cmd(cmd, args, actions),
+        Output::Sequence(sequence) => emit_sequence(sequence, actions),
+        // tap-holds are resolved before rules are looked up,
+        // and layer outputs switch layers in the `LayerStack`
+        Output::TapHold(_)
+        | Output::MomentaryLayer(_)
+        | Output::ToggleLayer(_)
+        | Output::OneShotLayer(_) => (),
     }
 }
 
-pub fn emit_sequence(sequence: &Vec<(&str, i32)>, virtual_device: &mut VirtualDevice) {
-    for e in sequence {
-        let code = KeyCode::from(e.0).0;
-        let event = virtual_event(code, e.1);
+pub fn emit_mapped_key(key: KeyCode, actions: &mut Vec<Action>) {
+    actions.push(Action::Keys(vec![(key, 1), (key, 0)]));
+}
+
+pub fn emit_cmd(cmd: &str, args: &[String], actions: &mut Vec<Action>) {
+    actions.push(Action::Cmd(cmd.to_string(), args.to_vec()));
+}
 
-        virtual_device.emit(&[event]).unwrap();
+pub fn emit_sequence(sequence: &[(KeyCode, i32)], actions: &mut Vec<Action>) {
+    for event in sequence {
+        actions.push(Action::Keys(vec![*event]));
     }
 }
 
@@ -42,12 +147,15 @@ pub fn send_signal_to_neovim(port: &str, msg: &str) {
 
     match std::net::TcpStream::connect(address.clone()) {
         Ok(mut stream) => {
-            println!("Successfully connected to server in port {address}");
+            debug!("Successfully connected to server in port {address}");
 
             let msg_bytes = msg.as_bytes();
 
-            std::io::Write::write(&mut stream, msg_bytes).unwrap();
-            println!("Sent {msg}, awaiting reply...");
+            if let Err(err) = std::io::Write::write(&mut stream, msg_bytes) {
+                warn!("Failed to send {msg} to neovim: {err}");
+                return;
+            }
+            debug!("Sent {msg}, awaiting reply...");
 
             let mut buffer = [0; 1024];
             let read_result = stream.read(&mut buffer);
@@ -57,26 +165,83 @@ pub fn send_signal_to_neovim(port: &str, msg: &str) {
                     let buffer = &buffer[..msg_length];
 
                     if buffer == msg_bytes {
-                        println!("Reply is ok!");
+                        debug!("Reply is ok!");
                     } else {
-                        let text = std::str::from_utf8(buffer).unwrap();
-                        println!("Unexpected reply: {text}");
-
-                        println!(
-                            "{} vs {}",
-                            std::str::from_utf8(buffer).unwrap(),
-                            std::str::from_utf8(msg_bytes).unwrap()
-                        );
+                        let text = String::from_utf8_lossy(buffer);
+                        warn!("Unexpected reply from neovim: {text} vs {msg}");
                     }
                 }
                 Err(e) => {
-                    println!("Failed to receive data: {e}");
+                    warn!("Failed to receive data from neovim: {e}");
                 }
             }
         }
         Err(e) => {
-            println!("tried to connect to: {address}");
-            println!("Failed to connect: {e}");
+            warn!("Failed to connect to neovim at {address}: {e}");
         }
     }
 }
+
+#[cfg(test)]
+mod rule_output_module_test {
+    use super::*;
+
+    #[test]
+    fn output_resolves_key_names_on_deserialize() {
+        let output: Output =
+            serde_json::from_str(r#"{ "Sequence": [["LeftCtrl", 1], ["f1", 1]] }"#).unwrap();
+        assert_eq!(
+            output,
+            Output::Sequence(vec![(KeyCode(29), 1), (KeyCode(59), 1)])
+        );
+
+        let err = serde_json::from_str::<Output>(r#"{ "Map": "LeftCrtl" }"#).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid key name \"LeftCrtl\""));
+    }
+
+    #[test]
+    fn output_serializes_with_key_names() {
+        let output = Output::Cmd(String::from("kitty"), vec![]);
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            r#"{"Cmd":["kitty",[]]}"#
+        );
+
+        let output = Output::Map(KeyCode(1));
+        assert_eq!(serde_json::to_string(&output).unwrap(), r#"{"Map":"ESC"}"#);
+    }
+
+    #[test]
+    fn tap_hold_options_have_defaults() {
+        let output: Output =
+            serde_json::from_str(r#"{ "TapHold": { "tap": "Esc", "hold": "LeftCtrl" } }"#).unwrap();
+
+        let Output::TapHold(tap_hold) = output else {
+            panic!("expected a TapHold output");
+        };
+        assert_eq!(*tap_hold.tap(), KeyCode(1));
+        assert_eq!(*tap_hold.hold(), Hold::Key(KeyCode(29)));
+        assert_eq!(*tap_hold.tapping_term(), 200);
+        assert!(!tap_hold.permissive_hold());
+        assert!(!tap_hold.hold_on_other_key_press());
+    }
+
+    #[test]
+    fn tap_hold_can_hold_a_layer() {
+        let output: Output = serde_json::from_str(
+            r#"{ "TapHold": { "tap": "Space", "hold": { "layer": "nav" } } }"#,
+        )
+        .unwrap();
+        assert_eq!(output.layer(), Some("nav"));
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            r#"{"TapHold":{"tap":"SPACE","hold":{"layer":"nav"},"tapping_term":200,"permissive_hold":false,"hold_on_other_key_press":false}}"#
+        );
+
+        let err = serde_json::from_str::<Output>(
+            r#"{ "TapHold": { "tap": "Space", "hold": { "lyaer": "nav" } } }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("expected { \"layer\": name }"));
+    }
+}
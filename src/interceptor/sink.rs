@@ -0,0 +1,184 @@
+use std::{fmt::Display, process::Command};
+
+use evdev::{uinput::VirtualDevice, EventType, InputEvent};
+
+use crate::{devices::output::virtual_event, stuffs::key_code::KeyCode};
+
+use super::rule_output::send_signal_to_neovim;
+
+/// Something the engine decided to send out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Key events sent together, in a single report.
+    Keys(Vec<(KeyCode, i32)>),
+    Cmd(String, Vec<String>),
+    Neovim(String),
+}
+
+impl Action {
+    fn input_events(events: &[(KeyCode, i32)]) -> Vec<InputEvent> {
+        events
+            .iter()
+            .map(|(code, value)| virtual_event(code.0, *value))
+            .collect()
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Keys(events) => {
+                let events: Vec<String> = events
+                    .iter()
+                    .map(|(code, value)| match value {
+                        0 => format!("{code} Up"),
+                        1 => format!("{code} Down"),
+                        _ => format!("{code} Hold"),
+                    })
+                    .collect();
+                write!(f, "{}", events.join(", "))
+            }
+            Action::Cmd(cmd, args) => {
+                write!(f, "`{cmd}")?;
+                for arg in args {
+                    write!(f, " {arg}")?;
+                }
+                write!(f, "`")
+            }
+            Action::Neovim(msg) => write!(f, "neovim \"{msg}\""),
+        }
+    }
+}
+
+/// Where the engine's actions go.
+pub trait OutputSink {
+    fn send(&mut self, action: &Action);
+
+    /// The port the neovim instance to send messages to listens on.
+    fn set_nvim_port(&mut self, _port: &str) {}
+}
+
+/// Sends keys through the uinput virtual device, runs commands and messages neovim.
+pub struct Uinput {
+    device: VirtualDevice,
+    nvim_port: String,
+}
+
+impl Uinput {
+    pub fn new(device: VirtualDevice) -> Self {
+        Self {
+            device,
+            nvim_port: String::new(),
+        }
+    }
+}
+
+impl OutputSink for Uinput {
+    fn send(&mut self, action: &Action) {
+        match action {
+            Action::Keys(events) => {
+                if let Err(err) = self.device.emit(&Action::input_events(events)) {
+                    error!("Failed to send keys: {err}");
+                }
+            }
+            Action::Cmd(cmd, args) => {
+                if let Err(err) = Command::new(cmd).args(args).spawn() {
+                    error!("Failed to run {action}: {err}");
+                }
+            }
+            Action::Neovim(msg) => send_signal_to_neovim(&self.nvim_port, msg),
+        }
+    }
+
+    fn set_nvim_port(&mut self, port: &str) {
+        self.nvim_port = port.to_string();
+    }
+}
+
+/// Keeps actions in memory, until taken.
+#[derive(Default)]
+pub struct Recorder {
+    actions: Vec<Action>,
+}
+
+impl Recorder {
+    pub fn take(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.actions)
+    }
+}
+
+impl OutputSink for Recorder {
+    fn send(&mut self, action: &Action) {
+        self.actions.push(action.clone());
+    }
+}
+
+/// Logs the input events and commands that would have gone out, for dry runs.
+pub struct Logger;
+
+impl OutputSink for Logger {
+    fn send(&mut self, action: &Action) {
+        match action {
+            Action::Keys(events) => {
+                for event in Action::input_events(events) {
+                    info!("Dry run: {}", describe(&event));
+                }
+            }
+            Action::Cmd(..) => info!("Dry run: would run {action}"),
+            Action::Neovim(msg) => info!("Dry run: would send \"{msg}\" to neovim"),
+        }
+    }
+}
+
+/// An `InputEvent` as `EV_KEY ESC 1`.
+fn describe(event: &InputEvent) -> String {
+    if event.event_type() == EventType::KEY {
+        format!("EV_KEY {} {}", KeyCode(event.code()), event.value())
+    } else {
+        format!("{event:?}")
+    }
+}
+
+#[cfg(test)]
+mod sink_module_test {
+    use super::*;
+
+    #[test]
+    fn recorder_keeps_actions_until_taken() {
+        let mut recorder = Recorder::default();
+        let esc = KeyCode::from("Esc");
+
+        recorder.send(&Action::Keys(vec![(esc, 1), (esc, 0)]));
+        recorder.send(&Action::Cmd(
+            "firefox".to_string(),
+            vec!["--new-window".to_string()],
+        ));
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Action::Keys(vec![(esc, 1), (esc, 0)]),
+                Action::Cmd("firefox".to_string(), vec!["--new-window".to_string()])
+            ]
+        );
+        assert!(recorder.take().is_empty());
+    }
+
+    #[test]
+    fn actions_display_like_key_events() {
+        let esc = KeyCode::from("Esc");
+        assert_eq!(
+            Action::Keys(vec![(esc, 1), (esc, 2), (esc, 0)]).to_string(),
+            "ESC Down, ESC Hold, ESC Up"
+        );
+        assert_eq!(
+            Action::Cmd("ls".to_string(), vec!["-a".to_string()]).to_string(),
+            "`ls -a`"
+        );
+    }
+
+    #[test]
+    fn describes_input_events_for_dry_runs() {
+        assert_eq!(describe(&virtual_event(1, 1)), "EV_KEY ESC 1");
+        assert_eq!(describe(&virtual_event(29, 0)), "EV_KEY LEFTCTRL 0");
+    }
+}
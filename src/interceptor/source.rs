@@ -0,0 +1,226 @@
+use std::{
+    fs, io,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use evdev::Device;
+
+use crate::devices::input::EventKindCheck;
+
+use super::TransmitSignal;
+
+/// A key event as `TransmitSignal::Key` carries it: keyboard alias, code, value and time.
+pub type KeyInput = (String, u16, i32, SystemTime);
+
+/// Where key events come from.
+pub trait InputSource {
+    /// Blocks until there are key events to read. `None` once the source is gone for good.
+    fn read(&mut self) -> io::Result<Option<Vec<KeyInput>>>;
+}
+
+/// Sends the key events read from `source` down `tx`, until either end goes away.
+pub fn pump(source: &mut dyn InputSource, tx: &Sender<TransmitSignal>) {
+    loop {
+        match source.read() {
+            Ok(Some(keys)) => {
+                for (alias, code, value, timestamp) in keys {
+                    if tx
+                        .send(TransmitSignal::Key(alias, code, value, timestamp))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+            Ok(None) => return,
+            Err(err) => warn!("Error reading key events: {err}"),
+        }
+    }
+}
+
+/// A keyboard under `/dev/input`.
+pub struct Evdev {
+    alias: String,
+    device: Device,
+}
+
+impl Evdev {
+    pub fn new(alias: &str, device: Device) -> Self {
+        Self {
+            alias: alias.to_string(),
+            device,
+        }
+    }
+}
+
+impl InputSource for Evdev {
+    fn read(&mut self) -> io::Result<Option<Vec<KeyInput>>> {
+        match self.device.fetch_events() {
+            // ENODEV, the device was unplugged
+            Err(err) if err.raw_os_error() == Some(19) => Ok(None),
+            Err(err) => Err(err),
+            Ok(events) => Ok(Some(
+                events
+                    .filter(EventKindCheck::is_type_key)
+                    .map(|ev| (self.alias.clone(), ev.code(), ev.value(), ev.timestamp()))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// Key events recorded in a file, one per line as `alias code value time`,
+/// the time in microseconds since the UNIX epoch. Blank lines and `#` comments are skipped.
+pub struct Recording {
+    keys: std::vec::IntoIter<KeyInput>,
+    /// How much faster than recorded the events are read, 0 for no waiting.
+    speed: f64,
+    last: Option<SystemTime>,
+}
+
+impl Recording {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read recording {path}: {err}"))?;
+        let keys =
+            parse_recording(&text).map_err(|err| format!("Invalid recording {path}: {err}"))?;
+        Ok(Self::new(keys))
+    }
+
+    pub fn new(keys: Vec<KeyInput>) -> Self {
+        Self {
+            keys: keys.into_iter(),
+            speed: 0.0,
+            last: None,
+        }
+    }
+
+    /// Waits between events as long as when they were recorded, divided by `speed`.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+}
+
+impl InputSource for Recording {
+    fn read(&mut self) -> io::Result<Option<Vec<KeyInput>>> {
+        let Some(key) = self.keys.next() else {
+            return Ok(None);
+        };
+        if let Some(last) = self.last.filter(|_| self.speed > 0.0) {
+            let gap = key.3.duration_since(last).unwrap_or_default();
+            thread::sleep(gap.div_f64(self.speed));
+        }
+        self.last = Some(key.3);
+        Ok(Some(vec![key]))
+    }
+}
+
+/// A key event as a line of a recording.
+pub fn format_key((alias, code, value, timestamp): &KeyInput) -> String {
+    let micros = timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+    format!("{alias} {code} {value} {micros}")
+}
+
+pub fn parse_recording(text: &str) -> Result<Vec<KeyInput>, String> {
+    let mut keys = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || format!("line {}, expected \"alias code value time\"", i + 1);
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [alias, code, value, micros] = fields[..] else {
+            return Err(invalid());
+        };
+        let code = code.parse().map_err(|_| invalid())?;
+        let Ok(value @ 0..=2) = value.parse() else {
+            return Err(format!("line {}, invalid key value \"{value}\"", i + 1));
+        };
+        let micros = micros.parse().map_err(|_| invalid())?;
+        keys.push((
+            alias.to_string(),
+            code,
+            value,
+            SystemTime::UNIX_EPOCH + Duration::from_micros(micros),
+        ));
+    }
+    Ok(keys)
+}
+
+/// Key events sent from elsewhere in the program, like tests.
+pub struct Channel {
+    rx: Receiver<KeyInput>,
+}
+
+impl Channel {
+    /// Returns the source, and where to send the key events it reads.
+    pub fn new() -> (Sender<KeyInput>, Self) {
+        let (tx, rx) = mpsc::channel();
+        (tx, Self { rx })
+    }
+}
+
+impl InputSource for Channel {
+    fn read(&mut self) -> io::Result<Option<Vec<KeyInput>>> {
+        Ok(self.rx.recv().ok().map(|key| vec![key]))
+    }
+}
+
+#[cfg(test)]
+mod source_module_test {
+    use crate::test_utilities::mipoch;
+
+    use super::*;
+
+    #[test]
+    fn reads_recordings() {
+        let text = "# a recording\nL1 58 1 0\n\nR1 35 0 40000\n";
+        assert_eq!(
+            parse_recording(text),
+            Ok(vec![
+                ("L1".to_string(), 58, 1, mipoch(0)),
+                ("R1".to_string(), 35, 0, mipoch(40)),
+            ])
+        );
+        let key = ("L1".to_string(), 58, 1, mipoch(1500));
+        assert_eq!(format_key(&key), "L1 58 1 1500000");
+        assert_eq!(parse_recording(&format_key(&key)), Ok(vec![key]));
+
+        assert_eq!(
+            parse_recording("L1 58 1 0\nL1 CAPSLOCK 0 40000"),
+            Err("line 2, expected \"alias code value time\"".to_string())
+        );
+        assert_eq!(
+            parse_recording("L1 58 1 0\nL1 58 3 1000"),
+            Err("line 2, invalid key value \"3\"".to_string())
+        );
+    }
+
+    #[test]
+    fn pumps_key_events_until_the_source_ends() {
+        let (keys, mut source) = Channel::new();
+        let (tx, rx) = mpsc::channel();
+
+        keys.send(("L1".to_string(), 58, 1, mipoch(0))).unwrap();
+        keys.send(("L1".to_string(), 58, 0, mipoch(40))).unwrap();
+        drop(keys);
+        pump(&mut source, &tx);
+
+        let values: Vec<i32> = rx
+            .try_iter()
+            .map(|signal| match signal {
+                TransmitSignal::Key(_, _, value, _) => value,
+                _ => panic!("expected key signals only"),
+            })
+            .collect();
+        assert_eq!(values, vec![1, 0]);
+    }
+}
@@ -5,13 +5,90 @@
 #[macro_use]
 extern crate getset;
 
+#[macro_use]
+mod log;
+
+mod cli;
+mod config;
 mod devices;
 mod event_processor;
 mod http_server;
 mod interceptor;
+mod rules;
 mod stuffs;
 mod test_utilities;
 
-fn main() {
-    interceptor::start();
+use std::process::ExitCode;
+
+use cli::{exit, Command};
+use interceptor::{Mode, StartError};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let cli = match cli::parse(&args) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("{err}\n\n{}", cli::USAGE);
+            return ExitCode::from(exit::USAGE);
+        }
+    };
+    log::set_level(*cli.log_level());
+
+    let mode = match cli.command() {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Command::ListDevices => {
+            devices::discovery::print(*cli.json());
+            return ExitCode::SUCCESS;
+        }
+        Command::Check => {
+            return match config::load(cli.config()) {
+                Ok(config) => {
+                    println!(
+                        "{} is valid: {} keyboards, {} rules, {} layers",
+                        cli.config(),
+                        config.keyboards().len(),
+                        config.rules().len(),
+                        config.layers().len()
+                    );
+                    ExitCode::SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    ExitCode::from(exit::CONFIG)
+                }
+            };
+        }
+        Command::Replay => {
+            return finish(interceptor::replay(
+                cli.config(),
+                cli.recording(),
+                *cli.speed(),
+            ));
+        }
+        Command::Run | Command::Record => Mode::Run,
+        Command::Monitor => Mode::Monitor,
+    };
+
+    let record = Some(cli.recording().as_str()).filter(|_| *cli.command() == Command::Record);
+    finish(interceptor::start(
+        cli.config(),
+        mode,
+        *cli.dry_run(),
+        *cli.wait(),
+        record,
+    ))
+}
+
+fn finish(result: Result<(), StartError>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::from(err.exit_code())
+        }
+    }
 }
@@ -0,0 +1,267 @@
+use std::time::{Duration, SystemTime};
+
+use crate::interceptor::rule_output::Output;
+
+use super::{
+    trie::Lookup,
+    trigger::{Step, Trigger},
+    Ruleset,
+};
+
+/// A rule that already matched, held back because a longer rule may still follow.
+#[derive(Debug, Clone, Getters)]
+pub struct Pending {
+    #[getset(get = "pub")]
+    trigger: Trigger,
+
+    #[getset(get = "pub")]
+    output: Output,
+
+    #[getset(get = "pub")]
+    deadline: SystemTime,
+}
+
+impl Pending {
+    /// Appends the steps of `sequence` that come after what it shares with the pending trigger.
+    /// Returns `None` when `sequence` adds nothing new.
+    fn extend(&self, sequence: &Trigger) -> Option<Trigger> {
+        let shared = self
+            .trigger
+            .steps()
+            .iter()
+            .zip(sequence.steps())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let new_steps = &sequence.steps()[shared..];
+        if new_steps.is_empty() {
+            return None;
+        }
+
+        let steps: Vec<Step> = self
+            .trigger
+            .steps()
+            .iter()
+            .chain(new_steps)
+            .cloned()
+            .collect();
+        Some(Trigger::new(steps))
+    }
+}
+
+/// What to do with a completed sequence.
+#[derive(Debug, Default, PartialEq, Eq, Getters)]
+pub struct Resolution {
+    /// Outputs to fire right away, in order.
+    #[getset(get = "pub")]
+    outputs: Vec<Output>,
+
+    /// Whether the sequence was used by a rule, now or by the one left pending.
+    #[getset(get = "pub")]
+    consumed: bool,
+}
+
+/// Decides when overlapping rules fire. A sequence that triggers a rule but is
+/// also the start of a longer one is held as `Pending` until the next key shows
+/// which rule was meant, or until `timeout` passes and the shorter rule fires.
+#[derive(Debug)]
+pub struct Disambiguator {
+    timeout: Duration,
+    pending: Option<Pending>,
+}
+
+impl Disambiguator {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn pending(&self) -> Option<&Pending> {
+        self.pending.as_ref()
+    }
+
+    /// Called on key press with the sequence so far.
+    /// Returns the pending output if the press can't lead to a longer rule.
+    pub fn press(&mut self, sequence: &Trigger, ruleset: &Ruleset) -> Option<Output> {
+        let extended = self.pending.as_ref()?.extend(sequence)?;
+        if ruleset.lookup(&extended) == Lookup::DeadEnd {
+            return self.pending.take().map(|p| p.output);
+        }
+        None
+    }
+
+    /// Called with a sequence completed on key release at `time`.
+    pub fn complete(
+        &mut self,
+        sequence: &Trigger,
+        time: SystemTime,
+        ruleset: &Ruleset,
+    ) -> Resolution {
+        let mut resolution = Resolution::default();
+
+        if let Some(pending) = self.pending.take() {
+            let Some(extended) = pending.extend(sequence) else {
+                self.pending = Some(pending);
+                resolution.consumed = true;
+                return resolution;
+            };
+
+            match ruleset.lookup(&extended) {
+                Lookup::Exact(i) => {
+                    resolution.outputs.push(ruleset.rules()[i].output().clone());
+                    resolution.consumed = true;
+                    return resolution;
+                }
+                Lookup::Ambiguous(i) => {
+                    self.hold(&extended, ruleset.rules()[i].output().clone(), time);
+                    resolution.consumed = true;
+                    return resolution;
+                }
+                // Only a prefix so far, the pending rule still fires on timeout.
+                Lookup::Prefix => {
+                    self.hold(&extended, pending.output, time);
+                    resolution.consumed = true;
+                    return resolution;
+                }
+                Lookup::DeadEnd => resolution.outputs.push(pending.output),
+            }
+        }
+
+        match ruleset.lookup(sequence) {
+            Lookup::Exact(i) => {
+                resolution.outputs.push(ruleset.rules()[i].output().clone());
+                resolution.consumed = true;
+            }
+            Lookup::Ambiguous(i) => {
+                self.hold(sequence, ruleset.rules()[i].output().clone(), time);
+                resolution.consumed = true;
+            }
+            Lookup::Prefix | Lookup::DeadEnd => (),
+        }
+        resolution
+    }
+
+    fn hold(&mut self, trigger: &Trigger, output: Output, time: SystemTime) {
+        self.pending = Some(Pending {
+            trigger: trigger.clone(),
+            output,
+            deadline: time + self.timeout,
+        });
+    }
+
+    /// Fires the pending rule once `now` is past its deadline.
+    pub fn expire(&mut self, now: SystemTime) -> Option<Output> {
+        match &self.pending {
+            Some(pending) if pending.deadline <= now => self.pending.take().map(|p| p.output),
+            _ => None,
+        }
+    }
+
+    /// When the pending rule fires, if any.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.pending.as_ref().map(|p| p.deadline)
+    }
+}
+
+#[cfg(test)]
+mod disambiguator_module_test {
+    use crate::{stuffs::key_code::KeyCode, test_utilities::mipoch};
+
+    use super::*;
+
+    fn ruleset() -> Ruleset {
+        serde_json::from_str(
+            r#"{
+                "L1 E, R1 K": { "Map": "A" },
+                "L1 E, R1 K, R1 J": { "Map": "B" },
+                "L1 E, L1 F": { "Map": "C" },
+                "R1 X": { "Map": "X" }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn trigger(input: &str) -> Trigger {
+        input.parse().unwrap()
+    }
+
+    fn map(key: &str) -> Output {
+        Output::Map(KeyCode::from(key))
+    }
+
+    #[test]
+    fn exact_matches_fire_right_away() {
+        let (ruleset, mut d) = (ruleset(), Disambiguator::new(Duration::from_millis(300)));
+
+        let resolution = d.complete(&trigger("L1 E, L1 F"), mipoch(0), &ruleset);
+        assert_eq!(resolution.outputs(), &vec![map("C")]);
+        assert!(resolution.consumed());
+        assert!(d.pending().is_none());
+    }
+
+    #[test]
+    fn shorter_rule_fires_after_timeout() {
+        let (ruleset, mut d) = (ruleset(), Disambiguator::new(Duration::from_millis(300)));
+
+        let resolution = d.complete(&trigger("L1 E, R1 K"), mipoch(100), &ruleset);
+        assert!(resolution.outputs().is_empty());
+        assert!(resolution.consumed());
+
+        assert_eq!(d.deadline(), Some(mipoch(400)));
+        assert_eq!(d.expire(mipoch(399)), None);
+        assert_eq!(d.expire(mipoch(400)), Some(map("A")));
+        assert!(d.pending().is_none());
+    }
+
+    #[test]
+    fn longer_rule_wins_when_next_key_continues_it() {
+        let (ruleset, mut d) = (ruleset(), Disambiguator::new(Duration::from_millis(300)));
+
+        d.complete(&trigger("L1 E, R1 K"), mipoch(100), &ruleset);
+        assert_eq!(d.press(&trigger("L1 E, R1 J"), &ruleset), None);
+
+        let resolution = d.complete(&trigger("L1 E, R1 J"), mipoch(200), &ruleset);
+        assert_eq!(resolution.outputs(), &vec![map("B")]);
+        assert!(d.pending().is_none());
+    }
+
+    #[test]
+    fn shorter_rule_fires_early_on_a_dead_end() {
+        let (ruleset, mut d) = (ruleset(), Disambiguator::new(Duration::from_millis(300)));
+
+        d.complete(&trigger("L1 E, R1 K"), mipoch(100), &ruleset);
+        assert_eq!(d.press(&trigger("R1 X"), &ruleset), Some(map("A")));
+
+        let resolution = d.complete(&trigger("R1 X"), mipoch(200), &ruleset);
+        assert_eq!(resolution.outputs(), &vec![map("X")]);
+    }
+
+    #[test]
+    fn dead_end_on_release_fires_both_rules_in_order() {
+        let (ruleset, mut d) = (ruleset(), Disambiguator::new(Duration::from_millis(300)));
+
+        d.complete(&trigger("L1 E, R1 K"), mipoch(100), &ruleset);
+
+        let resolution = d.complete(&trigger("R1 X"), mipoch(200), &ruleset);
+        assert_eq!(resolution.outputs(), &vec![map("A"), map("X")]);
+        assert!(resolution.consumed());
+    }
+
+    #[test]
+    fn releasing_keys_of_the_pending_sequence_keeps_waiting() {
+        let (ruleset, mut d) = (ruleset(), Disambiguator::new(Duration::from_millis(300)));
+
+        d.complete(&trigger("L1 E, R1 K"), mipoch(100), &ruleset);
+
+        let resolution = d.complete(&trigger("L1 E"), mipoch(150), &ruleset);
+        assert!(resolution.outputs().is_empty());
+        assert!(resolution.consumed());
+        assert!(d.pending().is_some());
+    }
+}
@@ -0,0 +1,356 @@
+pub mod disambiguator;
+pub mod trie;
+pub mod trigger;
+
+use std::fmt;
+
+use serde::{
+    de::{
+        self, value::MapAccessDeserializer, DeserializeSeed, IntoDeserializer, MapAccess, Visitor,
+    },
+    Deserialize, Deserializer,
+};
+
+use crate::{
+    config::timing::Windows,
+    interceptor::rule_output::{Output, TapHold},
+};
+
+use self::{
+    trie::{Lookup, RuleTrie},
+    trigger::{KeyPattern, Trigger},
+};
+
+#[derive(Getters, Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    #[getset(get = "pub")]
+    trigger: Trigger,
+
+    #[getset(get = "pub")]
+    output: Output,
+
+    /// Chord and orphan windows used to match this rule.
+    #[getset(get = "pub")]
+    timing: Windows,
+}
+
+/// A rule's value in the config: either an output,
+/// or `{ "output": ..., "timing": ... }` for an output with its own timing.
+struct TimedOutput {
+    output: Output,
+    timing: Windows,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimedOutputFields {
+    output: Output,
+    #[serde(default)]
+    timing: Windows,
+}
+
+/// Yields a key that was already read, then the rest of the map.
+struct Prepended<A> {
+    key: Option<String>,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Prepended<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.key.take() {
+            Some(key) => seed.deserialize(key.into_deserializer()).map(Some),
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimedOutput {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimedOutputVisitor;
+
+        impl<'de> Visitor<'de> for TimedOutputVisitor {
+            type Value = TimedOutput;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an output, or an output with timing")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let Some(key) = map.next_key::<String>()? else {
+                    return Err(de::Error::custom("expected an output"));
+                };
+                let timed = key == "output" || key == "timing";
+                let mut rest = Prepended {
+                    key: Some(key),
+                    map,
+                };
+
+                if timed {
+                    let fields = TimedOutputFields::deserialize(MapAccessDeserializer::new(rest))?;
+                    return Ok(TimedOutput {
+                        output: fields.output,
+                        timing: fields.timing,
+                    });
+                }
+
+                let output = Output::deserialize(MapAccessDeserializer::new(&mut rest))?;
+                if let Some(key) = rest.map.next_key::<String>()? {
+                    return Err(de::Error::custom(format!(
+                        "unexpected \"{key}\" next to the output, use {{ \"output\": ..., \"{key}\": ... }}"
+                    )));
+                }
+                Ok(TimedOutput {
+                    output,
+                    timing: Windows::default(),
+                })
+            }
+        }
+
+        deserializer.deserialize_map(TimedOutputVisitor)
+    }
+}
+
+/// Rules in the order they were declared, indexed by a `RuleTrie`.
+/// Deserialized from a map of trigger strings to outputs.
+#[derive(Default, Debug, Clone)]
+pub struct Ruleset {
+    rules: Vec<Rule>,
+    trie: RuleTrie,
+}
+
+impl Ruleset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule, failing if a structurally equal trigger already exists.
+    pub fn add(&mut self, trigger: Trigger, output: Output, timing: Windows) -> Result<(), String> {
+        if self.rules.iter().any(|r| r.trigger == trigger) {
+            return Err(format!("Duplicate trigger \"{trigger}\""));
+        }
+        if output.is_key_level() {
+            if trigger.single_key().is_none() {
+                let kind = match output {
+                    Output::TapHold(_) => "TapHold",
+                    _ => "MomentaryLayer",
+                };
+                return Err(format!(
+                    "{kind} needs a single key trigger, found \"{trigger}\""
+                ));
+            }
+        } else {
+            self.trie.insert(&trigger, self.rules.len());
+        }
+        self.rules.push(Rule {
+            trigger,
+            output,
+            timing,
+        });
+        Ok(())
+    }
+
+    /// Tells whether the concrete `sequence` triggers a rule,
+    /// could still grow into one, or neither.
+    pub fn lookup(&self, sequence: &Trigger) -> Lookup {
+        self.trie.lookup(sequence)
+    }
+
+    /// Returns `true` if the concrete `key` is the first key of some sequence rule.
+    pub fn can_start(&self, key: &KeyPattern) -> bool {
+        self.trie.can_start(key)
+    }
+
+    /// Finds the rule triggered by the concrete `sequence`.
+    /// On overlap, the rule declared first wins.
+    pub fn find(&self, sequence: &Trigger) -> Option<&Rule> {
+        self.lookup(sequence).rule().map(|i| &self.rules[i])
+    }
+
+    /// Keys with a `TapHold` output. These are kept out of the trie,
+    /// since they are resolved before sequences are matched.
+    pub fn tap_holds(&self) -> Vec<(KeyPattern, TapHold)> {
+        self.rules
+            .iter()
+            .filter_map(|r| match (&r.output, r.trigger.single_key()) {
+                (Output::TapHold(tap_hold), Some(key)) => Some((key.clone(), tap_hold.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Keys with a `MomentaryLayer` output, kept out of the trie like tap-holds.
+    pub fn momentary_layers(&self) -> Vec<(KeyPattern, String)> {
+        self.rules
+            .iter()
+            .filter_map(|r| match (&r.output, r.trigger.single_key()) {
+                (Output::MomentaryLayer(name), Some(key)) => Some((key.clone(), name.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Triggers of the rules that override the chord or orphan window.
+    pub fn timed_triggers(&self) -> Vec<(Trigger, Windows)> {
+        self.rules
+            .iter()
+            .filter(|r| !r.timing.is_empty())
+            .map(|r| (r.trigger.clone(), r.timing))
+            .collect()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Rule> {
+        self.rules.get(index)
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl<'de> Deserialize<'de> for Ruleset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RulesetVisitor;
+
+        impl<'de> Visitor<'de> for RulesetVisitor {
+            type Value = Ruleset;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of triggers to outputs")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut ruleset = Ruleset::new();
+                while let Some((trigger, timed)) = map.next_entry::<Trigger, TimedOutput>()? {
+                    ruleset
+                        .add(trigger, timed.output, timed.timing)
+                        .map_err(de::Error::custom)?;
+                }
+                Ok(ruleset)
+            }
+        }
+
+        deserializer.deserialize_map(RulesetVisitor)
+    }
+}
+
+#[cfg(test)]
+mod rules_module_test {
+    use crate::stuffs::key_code::KeyCode;
+
+    use super::*;
+
+    fn trigger(input: &str) -> Trigger {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_rules_structurally() {
+        let ruleset: Ruleset = serde_json::from_str(
+            r#"{
+                "L1 CAPSLOCK": { "Map": "Esc" },
+                "L1 CAPSLOCK , R1 h": { "Map": "Left" },
+                "[R1 B, L1 A]": { "Map": "PlayPause" }
+            }"#,
+        )
+        .unwrap();
+
+        let find = |s: &str| ruleset.find(&trigger(s)).map(|r| r.output().clone());
+        assert_eq!(find("L1 CAPSLOCK"), Some(Output::Map(KeyCode::from("Esc"))));
+        assert_eq!(
+            find("L1 CAPSLOCK, R1 H"),
+            Some(Output::Map(KeyCode::from("Left")))
+        );
+        assert_eq!(
+            find("[L1 A, R1 B]"),
+            Some(Output::Map(KeyCode::from("PlayPause")))
+        );
+        assert_eq!(find("L1 A, R1 B"), None);
+        assert!(ruleset.find(&Trigger::default()).is_none());
+    }
+
+    #[test]
+    fn tap_hold_rules_need_a_single_key() {
+        let ruleset: Ruleset = serde_json::from_str(
+            r#"{ "L1 CAPSLOCK": { "TapHold": { "tap": "Esc", "hold": "LeftCtrl" } } }"#,
+        )
+        .unwrap();
+        assert_eq!(ruleset.tap_holds().len(), 1);
+        assert!(ruleset.find(&trigger("L1 CAPSLOCK")).is_none());
+
+        let err = serde_json::from_str::<Ruleset>(
+            r#"{ "L1 A, L1 B": { "TapHold": { "tap": "Esc", "hold": "LeftCtrl" } } }"#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("TapHold needs a single key trigger"));
+    }
+
+    #[test]
+    fn momentary_layer_rules_need_a_single_key() {
+        let ruleset: Ruleset =
+            serde_json::from_str(r#"{ "L1 TAB": { "MomentaryLayer": "nav" } }"#).unwrap();
+        assert_eq!(ruleset.momentary_layers().len(), 1);
+        assert!(ruleset.find(&trigger("L1 TAB")).is_none());
+
+        let err =
+            serde_json::from_str::<Ruleset>(r#"{ "[L1 A, L1 B]": { "MomentaryLayer": "nav" } }"#)
+                .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("MomentaryLayer needs a single key trigger"));
+    }
+
+    #[test]
+    fn rules_can_override_timing() {
+        let ruleset: Ruleset = serde_json::from_str(
+            r#"{
+                "L1 A": { "Map": "B" },
+                "[L1 A, R1 B]": { "output": { "Map": "C" }, "timing": { "chord_window": 80 } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            ruleset.timed_triggers(),
+            vec![(trigger("[L1 A, R1 B]"), Windows::new(Some(80), None))]
+        );
+        assert_eq!(
+            ruleset.find(&trigger("[L1 A, R1 B]")).unwrap().output(),
+            &Output::Map(KeyCode::from("C"))
+        );
+    }
+
+    #[test]
+    fn duplicate_triggers_are_rejected() {
+        let err = serde_json::from_str::<Ruleset>(
+            "{\n\"L1 A, R1 B\": { \"Map\": \"C\" },\n\"L1 A,R1 B\": { \"Map\": \"D\" } }",
+        )
+        .unwrap_err();
+
+        assert_eq!(err.line(), 3);
+        assert!(err
+            .to_string()
+            .starts_with("Duplicate trigger \"L1 A, R1 B\""));
+    }
+}
@@ -0,0 +1,163 @@
+use super::trigger::{KeyPattern, Step, Trigger};
+
+/// Where a concrete sequence stands against the rules in a `RuleTrie`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Lookup {
+    /// The sequence triggers the rule at this index and no longer rule starts with it.
+    Exact(usize),
+    /// The sequence triggers the rule at this index, but is also a prefix of longer rules.
+    Ambiguous(usize),
+    /// No rule is triggered yet, but some longer rule starts with the sequence.
+    Prefix,
+    /// No rule is triggered by or starts with the sequence.
+    DeadEnd,
+}
+
+impl Lookup {
+    /// Index of the triggered rule, if any.
+    pub fn rule(&self) -> Option<usize> {
+        match self {
+            Lookup::Exact(i) | Lookup::Ambiguous(i) => Some(*i),
+            Lookup::Prefix | Lookup::DeadEnd => None,
+        }
+    }
+
+    /// Returns `true` if more keys could still lead to a rule.
+    pub fn can_continue(&self) -> bool {
+        matches!(self, Lookup::Ambiguous(_) | Lookup::Prefix)
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+struct Node {
+    children: Vec<(Step, Node)>,
+    rule: Option<usize>,
+}
+
+/// Trie over trigger steps. Optional keys are expanded on insert,
+/// so every path in the trie is a sequence of required-only steps.
+#[derive(Default, Debug, Clone)]
+pub struct RuleTrie {
+    root: Node,
+}
+
+impl RuleTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts `trigger` for the rule at `index`.
+    /// When several rules end on the same path, the earliest inserted one wins.
+    pub fn insert(&mut self, trigger: &Trigger, index: usize) {
+        for steps in trigger.expand() {
+            let mut node = &mut self.root;
+            for step in steps {
+                let position = node.children.iter().position(|(s, _)| *s == step);
+                let position = position.unwrap_or_else(|| {
+                    node.children.push((step, Node::default()));
+                    node.children.len() - 1
+                });
+                node = &mut node.children[position].1;
+            }
+            node.rule.get_or_insert(index);
+        }
+    }
+
+    /// Returns `true` if some rule's first step accepts the concrete `key`,
+    /// alone or as part of a chord.
+    pub fn can_start(&self, key: &KeyPattern) -> bool {
+        self.root
+            .children
+            .iter()
+            .any(|(step, _)| step.keys().iter().any(|k| k.matches(key)))
+    }
+
+    /// Walks the concrete `sequence` through the trie.
+    pub fn lookup(&self, sequence: &Trigger) -> Lookup {
+        if sequence.is_empty() {
+            return Lookup::DeadEnd;
+        }
+
+        let mut nodes = vec![&self.root];
+        for step in sequence.steps() {
+            nodes = nodes
+                .iter()
+                .flat_map(|n| n.children.iter())
+                .filter(|(pattern, _)| pattern.matches(step))
+                .map(|(_, node)| node)
+                .collect();
+        }
+
+        let rule = nodes.iter().filter_map(|n| n.rule).min();
+        let has_longer = nodes.iter().any(|n| !n.children.is_empty());
+
+        match (rule, has_longer) {
+            (Some(i), false) => Lookup::Exact(i),
+            (Some(i), true) => Lookup::Ambiguous(i),
+            (None, true) => Lookup::Prefix,
+            (None, false) => Lookup::DeadEnd,
+        }
+    }
+}
+
+#[cfg(test)]
+mod trie_module_test {
+    use super::*;
+
+    fn trie(triggers: &[&str]) -> RuleTrie {
+        let mut trie = RuleTrie::new();
+        for (i, trigger) in triggers.iter().enumerate() {
+            trie.insert(&trigger.parse().unwrap(), i);
+        }
+        trie
+    }
+
+    fn lookup(trie: &RuleTrie, sequence: &str) -> Lookup {
+        trie.lookup(&sequence.parse().unwrap())
+    }
+
+    #[test]
+    fn knows_exact_prefix_and_dead_end() {
+        let trie = trie(&["L1 E, R1 K", "L1 E, R1 K, R1 J", "L1 E, L1 F"]);
+
+        assert_eq!(lookup(&trie, "L1 E"), Lookup::Prefix);
+        assert_eq!(lookup(&trie, "L1 E, R1 K"), Lookup::Ambiguous(0));
+        assert_eq!(lookup(&trie, "L1 E, R1 K, R1 J"), Lookup::Exact(1));
+        assert_eq!(lookup(&trie, "L1 E, L1 F"), Lookup::Exact(2));
+        assert_eq!(lookup(&trie, "L1 E, R1 J"), Lookup::DeadEnd);
+        assert_eq!(lookup(&trie, "R1 K"), Lookup::DeadEnd);
+        assert_eq!(trie.lookup(&Trigger::default()), Lookup::DeadEnd);
+    }
+
+    #[test]
+    fn handles_chords_wildcards_and_optional_keys() {
+        let trie = trie(&["[L1 H, R1 J], K", "L1 LEFTSHIFT?, R1 J"]);
+
+        assert_eq!(lookup(&trie, "[R1 J, L1 H]"), Lookup::Prefix);
+        assert_eq!(lookup(&trie, "[R1 J, L1 H], L1 K"), Lookup::Exact(0));
+        assert_eq!(lookup(&trie, "L1 LEFTSHIFT"), Lookup::Prefix);
+        assert_eq!(lookup(&trie, "L1 LEFTSHIFT, R1 J"), Lookup::Exact(1));
+        assert_eq!(lookup(&trie, "R1 J"), Lookup::Exact(1));
+    }
+
+    #[test]
+    fn knows_which_keys_can_start_a_rule() {
+        let trie = trie(&["[L1 H, R1 J], K", "L1 LEFTSHIFT?, R1 X", "L1 A, L1 B"]);
+        let can_start = |device, code| trie.can_start(&KeyPattern::new(Some(device), code));
+
+        assert!(can_start("R1", "J"));
+        assert!(can_start("L1", "LEFTSHIFT"));
+        assert!(can_start("R1", "X"));
+        assert!(can_start("L1", "A"));
+        assert!(!can_start("L1", "B"));
+        assert!(!can_start("R1", "K"));
+    }
+
+    #[test]
+    fn earliest_rule_wins_on_overlap() {
+        let trie = trie(&["L1 A, B", "L1 A, L1 B"]);
+
+        assert_eq!(lookup(&trie, "L1 A, L1 B"), Lookup::Exact(0));
+        assert_eq!(lookup(&trie, "L1 A, R1 B"), Lookup::Exact(0));
+    }
+}
@@ -0,0 +1,501 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stuffs::{key_code::KeyCode, key_state::KeyState, keyboard_event::KeyboardEvent};
+
+/// A single key of a trigger, written as `L1 CAPSLOCK`.
+/// The device alias can be left out to match the key on any device (`CAPSLOCK`),
+/// a trailing `?` marks the key as optional (`L1 LEFTSHIFT?`)
+/// and a leading `!` stands for the key being released (`!L1 CAPSLOCK`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Getters)]
+pub struct KeyPattern {
+    #[getset(get = "pub")]
+    device: Option<String>,
+
+    #[getset(get = "pub")]
+    code: KeyCode,
+
+    #[getset(get = "pub")]
+    released: bool,
+
+    #[getset(get = "pub")]
+    optional: bool,
+}
+
+impl KeyPattern {
+    pub fn new<T: Into<KeyCode>>(device: Option<&str>, code: T) -> Self {
+        Self {
+            device: device.map(ToString::to_string),
+            code: code.into(),
+            released: false,
+            optional: false,
+        }
+    }
+
+    /// Returns `true` if this pattern accepts the concrete `key`.
+    pub fn matches(&self, key: &KeyPattern) -> bool {
+        self.code == key.code
+            && self.released == key.released
+            && (self.device.is_none() || self.device == key.device)
+    }
+}
+
+impl<'a> From<&KeyboardEvent<'a>> for KeyPattern {
+    fn from(event: &KeyboardEvent<'a>) -> Self {
+        let key = event.key();
+        Self {
+            released: *event.value() == KeyState::Up,
+            ..Self::new(Some(key.device().alias()), *key.code())
+        }
+    }
+}
+
+impl Display for KeyPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.released {
+            write!(f, "!")?;
+        }
+        if let Some(device) = &self.device {
+            write!(f, "{device} ")?;
+        }
+        write!(f, "{}", self.code)?;
+        if self.optional {
+            write!(f, "?")?;
+        }
+        Ok(())
+    }
+}
+
+/// Keys pressed together. A lone key is a chord of one.
+/// Keys are kept sorted, so the order they are written in doesn't matter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Getters)]
+pub struct Step {
+    #[getset(get = "pub")]
+    keys: Vec<KeyPattern>,
+}
+
+impl Step {
+    pub fn new(mut keys: Vec<KeyPattern>) -> Self {
+        keys.sort();
+        keys.dedup();
+        Self { keys }
+    }
+
+    /// Returns `true` if every key of the concrete step is accepted by exactly one pattern.
+    pub fn matches(&self, concrete: &Step) -> bool {
+        fn assign(patterns: &[&KeyPattern], keys: &mut Vec<&KeyPattern>) -> bool {
+            let Some((pattern, rest)) = patterns.split_first() else {
+                return keys.is_empty();
+            };
+            for i in 0..keys.len() {
+                if pattern.matches(keys[i]) {
+                    let key = keys.remove(i);
+                    if assign(rest, keys) {
+                        return true;
+                    }
+                    keys.insert(i, key);
+                }
+            }
+            false
+        }
+
+        let patterns = self.keys.iter().collect::<Vec<_>>();
+        let mut keys = concrete.keys.iter().collect::<Vec<_>>();
+        patterns.len() == keys.len() && assign(&patterns, &mut keys)
+    }
+
+    /// Every variant of this step with optional keys either kept or dropped.
+    fn variants(&self) -> Vec<Vec<KeyPattern>> {
+        let mut variants = vec![vec![]];
+        for key in &self.keys {
+            let mut required = key.clone();
+            required.optional = false;
+
+            let mut with_key = variants.clone();
+            for variant in &mut with_key {
+                variant.push(required.clone());
+            }
+            if !key.optional {
+                variants.clear();
+            }
+            variants.append(&mut with_key);
+        }
+        variants
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.keys.len() == 1 {
+            return write!(f, "{}", self.keys[0]);
+        }
+
+        write!(
+            f,
+            "[{}]",
+            self.keys
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+/// A parsed rule trigger such as `L1 CAPSLOCK, [R1 H, R1 J]`.
+/// `SequenceManager` produces concrete triggers (every key has a device and
+/// nothing is optional), which rule triggers are then matched against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Getters, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Trigger {
+    #[getset(get = "pub")]
+    steps: Vec<Step>,
+}
+
+impl Trigger {
+    pub fn new(steps: Vec<Step>) -> Self {
+        Self { steps }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Every concrete shape this trigger accepts, with optional keys resolved.
+    pub fn expand(&self) -> Vec<Vec<Step>> {
+        let mut expansions: Vec<Vec<Step>> = vec![vec![]];
+        for step in &self.steps {
+            let mut next = vec![];
+            for variant in step.variants() {
+                for expansion in &expansions {
+                    let mut expansion = expansion.clone();
+                    if !variant.is_empty() {
+                        expansion.push(Step::new(variant.clone()));
+                    }
+                    next.push(expansion);
+                }
+            }
+            expansions = next;
+        }
+        expansions.retain(|e| !e.is_empty());
+        expansions
+    }
+
+    /// Returns `true` if the concrete `sequence` satisfies this trigger.
+    pub fn matches(&self, sequence: &Trigger) -> bool {
+        self.expand().iter().any(|steps| {
+            steps.len() == sequence.steps.len()
+                && steps.iter().zip(&sequence.steps).all(|(p, s)| p.matches(s))
+        })
+    }
+
+    /// The key of a trigger made of exactly one plain key press.
+    pub fn single_key(&self) -> Option<&KeyPattern> {
+        match self.steps.as_slice() {
+            [step] => match step.keys.as_slice() {
+                [key] if !key.released && !key.optional => Some(key),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Every key of every step.
+    pub fn keys(&self) -> impl Iterator<Item = &KeyPattern> {
+        self.steps.iter().flat_map(|s| s.keys.iter())
+    }
+
+    /// Device aliases referenced by this trigger.
+    pub fn devices(&self) -> impl Iterator<Item = &String> {
+        self.keys().filter_map(|k| k.device.as_ref())
+    }
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.steps
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+impl From<Trigger> for String {
+    fn from(trigger: Trigger) -> Self {
+        trigger.to_string()
+    }
+}
+
+impl TryFrom<String> for Trigger {
+    type Error = TriggerParseError;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = TriggerParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Parser::new(input).parse_trigger()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TriggerParseError {
+    trigger: String,
+    column: usize,
+    message: String,
+}
+
+impl Display for TriggerParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at column {} of trigger \"{}\"",
+            self.message, self.column, self.trigger
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Question,
+    Bang,
+    Word(String),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open => write!(f, "\"[\""),
+            Token::Close => write!(f, "\"]\""),
+            Token::Comma => write!(f, "\",\""),
+            Token::Question => write!(f, "\"?\""),
+            Token::Bang => write!(f, "\"!\""),
+            Token::Word(word) => write!(f, "\"{word}\""),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        let mut tokens = vec![];
+        let mut word_start = None;
+
+        for (i, c) in input.char_indices().chain([(input.len(), ' ')]) {
+            let token = match c {
+                '[' => Some(Token::Open),
+                ']' => Some(Token::Close),
+                ',' => Some(Token::Comma),
+                '?' => Some(Token::Question),
+                '!' => Some(Token::Bang),
+                c if c.is_whitespace() => None,
+                _ => {
+                    word_start.get_or_insert(i);
+                    continue;
+                }
+            };
+            if let Some(start) = word_start.take() {
+                tokens.push((start + 1, Token::Word(input[start..i].to_string())));
+            }
+            if let Some(token) = token {
+                tokens.push((i + 1, token));
+            }
+        }
+
+        Self {
+            input,
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error<S: Into<String>>(&self, column: usize, message: S) -> TriggerParseError {
+        TriggerParseError {
+            trigger: self.input.to_string(),
+            column,
+            message: message.into(),
+        }
+    }
+
+    fn unexpected(&self, token: Option<(usize, Token)>, expected: &str) -> TriggerParseError {
+        match token {
+            Some((column, token)) => {
+                self.error(column, format!("Expected {expected}, found {token}"))
+            }
+            None => self.error(self.input.len() + 1, format!("Expected {expected}")),
+        }
+    }
+
+    fn parse_trigger(&mut self) -> Result<Trigger, TriggerParseError> {
+        let mut steps = vec![self.parse_step()?];
+        while self.accept(&Token::Comma) {
+            steps.push(self.parse_step()?);
+        }
+
+        match self.next() {
+            None => Ok(Trigger::new(steps)),
+            token => Err(self.unexpected(token, "\",\"")),
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, TriggerParseError> {
+        if !self.accept(&Token::Open) {
+            return Ok(Step::new(vec![self.parse_key()?]));
+        }
+
+        let mut keys = vec![self.parse_key()?];
+        loop {
+            match self.next() {
+                Some((_, Token::Comma)) => keys.push(self.parse_key()?),
+                Some((_, Token::Close)) => return Ok(Step::new(keys)),
+                token => return Err(self.unexpected(token, "\",\" or \"]\"")),
+            }
+        }
+    }
+
+    fn parse_key(&mut self) -> Result<KeyPattern, TriggerParseError> {
+        let released = self.accept(&Token::Bang);
+
+        let (mut column, mut name) = self.expect_word()?;
+        let mut device = None;
+        if let Some(Token::Word(_)) = self.peek() {
+            device = Some(name);
+            (column, name) = self.expect_word()?;
+        }
+
+        let optional = self.accept(&Token::Question);
+
+        let code = name
+            .parse::<KeyCode>()
+            .map_err(|message| self.error(column, message))?;
+
+        Ok(KeyPattern {
+            device,
+            code,
+            released,
+            optional,
+        })
+    }
+
+    fn expect_word(&mut self) -> Result<(usize, String), TriggerParseError> {
+        match self.next() {
+            Some((column, Token::Word(word))) => Ok((column, word)),
+            token => Err(self.unexpected(token, "a key")),
+        }
+    }
+
+    /// Consumes the next token if it is `token`.
+    fn accept(&mut self, token: &Token) -> bool {
+        let accepted = self.peek() == Some(token);
+        if accepted {
+            self.next();
+        }
+        accepted
+    }
+}
+
+#[cfg(test)]
+mod trigger_module_test {
+    use super::*;
+
+    fn trigger(input: &str) -> Trigger {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn can_parse_and_print_triggers() {
+        assert_eq!(trigger("L1 CAPSLOCK").to_string(), "L1 CAPSLOCK");
+        assert_eq!(
+            trigger("l1 capslock ,R1 h").to_string(),
+            "l1 CAPSLOCK, R1 H"
+        );
+        assert_eq!(
+            trigger("[R1 J,L1 H], esc, L1 leftshift ?").to_string(),
+            "[L1 H, R1 J], ESC, L1 LEFTSHIFT?"
+        );
+    }
+
+    #[test]
+    fn chord_order_and_whitespace_do_not_matter() {
+        assert_eq!(trigger("[L1 A, R1 B]"), trigger("[ R1 B,L1   A ]"));
+        assert_ne!(trigger("L1 A, R1 B"), trigger("R1 B, L1 A"));
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_offending_column() {
+        let err = "L1 CAPSLOCK, R1 LeftCrtl".parse::<Trigger>().unwrap_err();
+        assert_eq!(err.column, 17);
+        assert_eq!(err.message, "Invalid key name \"LeftCrtl\"");
+
+        let err = "[L1 A, R1 B".parse::<Trigger>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected \",\" or \"]\" at column 12 of trigger \"[L1 A, R1 B\""
+        );
+
+        let err = "L1 A,".parse::<Trigger>().unwrap_err();
+        assert_eq!(err.message, "Expected a key");
+
+        let err = "L1 A B".parse::<Trigger>().unwrap_err();
+        assert_eq!(err.message, "Expected \",\", found \"B\"");
+    }
+
+    #[test]
+    fn matches_structurally() {
+        let sequence = trigger("[R1 J, L1 H], R1 K");
+
+        assert!(trigger("[L1 H, R1 J], R1 K").matches(&sequence));
+        assert!(trigger("[H, J], K").matches(&sequence));
+        assert!(!trigger("L1 H, R1 J, R1 K").matches(&sequence));
+        assert!(!trigger("[L1 H, L1 J], R1 K").matches(&sequence));
+    }
+
+    #[test]
+    fn released_keys_are_distinct_from_pressed_ones() {
+        let rule = trigger("L1 CAPSLOCK, R1 H, !L1 CAPSLOCK");
+
+        assert_eq!(rule.to_string(), "L1 CAPSLOCK, R1 H, !L1 CAPSLOCK");
+        assert!(rule.matches(&trigger("L1 CAPSLOCK, R1 H, ! L1 CAPSLOCK")));
+        assert!(!rule.matches(&trigger("L1 CAPSLOCK, R1 H, L1 CAPSLOCK")));
+    }
+
+    #[test]
+    fn optional_keys_may_be_left_out() {
+        let rule = trigger("L1 LEFTSHIFT?, [R1 J, R1 K?]");
+
+        assert!(rule.matches(&trigger("L1 LEFTSHIFT, R1 J")));
+        assert!(rule.matches(&trigger("R1 J")));
+        assert!(rule.matches(&trigger("[R1 J, R1 K]")));
+        assert!(rule.matches(&trigger("L1 LEFTSHIFT, [R1 K, R1 J]")));
+        assert!(!rule.matches(&trigger("L1 LEFTSHIFT")));
+        assert_eq!(rule.expand().len(), 4);
+    }
+}
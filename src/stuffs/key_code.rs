@@ -1,4 +1,6 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
 
 const KEY_ARRAY: &[(&str, u16); 548] = &[
     ("KEY_RESERVED", 0),
@@ -551,7 +553,8 @@ const KEY_ARRAY: &[(&str, u16); 548] = &[
     ("BTN_TRIGGER_HAPPY40", 0x2e7),
 ];
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
 pub struct KeyCode(pub u16);
 
 impl Display for KeyCode {
@@ -577,15 +580,37 @@ impl From<u16> for KeyCode {
     }
 }
 
-impl From<&str> for KeyCode {
-    fn from(input: &str) -> Self {
-        let input = process_key_name_input(input);
-        for (name, code) in KEY_ARRAY {
-            if *name == input {
-                return Self(*code);
+impl FromStr for KeyCode {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let name = process_key_name_input(input);
+        for (key_name, code) in KEY_ARRAY {
+            if *key_name == name {
+                return Ok(Self(*code));
             }
         }
-        panic!("Invalid Input Key Name");
+        Err(format!("Invalid key name \"{input}\""))
+    }
+}
+
+impl TryFrom<String> for KeyCode {
+    type Error = String;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl From<KeyCode> for String {
+    fn from(code: KeyCode) -> Self {
+        code.to_string()
+    }
+}
+
+impl From<&str> for KeyCode {
+    fn from(input: &str) -> Self {
+        input.parse().expect("Invalid Input Key Name")
     }
 }
 
@@ -609,4 +634,14 @@ mod key_code_module_test {
         let keycode: KeyCode = 32.into();
         assert_eq!(keycode.to_string(), "D");
     }
+
+    #[test]
+    fn can_parse_key_names() {
+        assert_eq!("LeftCtrl".parse::<KeyCode>(), Ok(KeyCode(29)));
+        assert_eq!("btn_left".parse::<KeyCode>(), Ok(KeyCode(0x110)));
+        assert_eq!(
+            "LeftCrtl".parse::<KeyCode>(),
+            Err(String::from("Invalid key name \"LeftCrtl\""))
+        );
+    }
 }
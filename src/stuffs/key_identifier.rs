@@ -4,6 +4,7 @@ use super::{key_code::KeyCode, keyboard::Keyboard};
 
 #[derive(Debug, PartialEq, Eq, Getters, PartialOrd, Ord, Clone)]
 pub struct KeyIdentifier<'a> {
+    #[getset(get = "pub")]
     device: &'a Keyboard,
 
     #[getset(get = "pub")]
@@ -23,6 +24,11 @@ impl<'a> KeyIdentifier<'a> {
             device,
         }
     }
+
+    /// Returns `true` if the device treats this key as a modifier.
+    pub fn is_modifier(&self) -> bool {
+        self.device.modifiers().contains(self.code)
+    }
 }
 
 #[cfg(test)]
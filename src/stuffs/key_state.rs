@@ -24,6 +24,16 @@ impl From<i32> for KeyState {
     }
 }
 
+impl From<KeyState> for i32 {
+    fn from(state: KeyState) -> Self {
+        match state {
+            KeyState::Up => 0,
+            KeyState::Down => 1,
+            KeyState::Hold => 2,
+        }
+    }
+}
+
 impl From<&str> for KeyState {
     fn from(input: &str) -> Self {
         match input.to_uppercase().as_str() {
@@ -1,4 +1,24 @@
-#[derive(Getters, PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
+use serde::Deserialize;
+
+use crate::{config::timing::Windows, devices::matcher::DeviceMatcher};
+
+use super::{key_code::KeyCode, modifier::ModifierSet};
+
+/// What a keyboard does with a key before any rule sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyTreatment {
+    /// Goes through tap-hold, layers and rules.
+    Intercept,
+    /// Goes to the virtual device as it is, skipping every stage.
+    Passthrough,
+    /// Dropped.
+    Block,
+    /// Can be part of rules, but never goes out on its own.
+    Swallow,
+}
+
+#[derive(Deserialize, Getters, PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
 pub struct Keyboard {
     #[getset(get = "pub")]
     alias: String,
@@ -6,8 +26,38 @@ pub struct Keyboard {
     #[getset(get = "pub")]
     name: String,
 
+    /// Physical path of the device, the same as `"match": { "path": ... }`.
+    #[serde(default)]
     #[getset(get = "pub")]
     path: String,
+
+    #[serde(default, rename = "match")]
+    matcher: DeviceMatcher,
+
+    /// Chord and orphan windows for keys of this keyboard.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    timing: Windows,
+
+    #[serde(default)]
+    #[getset(get = "pub")]
+    modifiers: ModifierSet,
+
+    #[serde(default)]
+    #[getset(get = "pub")]
+    passthrough: Vec<KeyCode>,
+
+    #[serde(default)]
+    #[getset(get = "pub")]
+    block: Vec<KeyCode>,
+
+    #[serde(default = "default_swallow")]
+    #[getset(get = "pub")]
+    swallow: Vec<KeyCode>,
+}
+
+fn default_swallow() -> Vec<KeyCode> {
+    vec![KeyCode::from("CapsLock")]
 }
 
 impl Keyboard {
@@ -16,6 +66,33 @@ impl Keyboard {
             alias: alias.into(),
             name: name.into(),
             path: path.into(),
+            matcher: DeviceMatcher::default(),
+            timing: Windows::default(),
+            modifiers: ModifierSet::default(),
+            passthrough: vec![],
+            block: vec![],
+            swallow: default_swallow(),
+        }
+    }
+
+    /// Picks the input device of this keyboard, from `"match"` and `"path"`.
+    pub fn device_matcher(&self) -> DeviceMatcher {
+        let mut matcher = self.matcher.clone();
+        matcher.set_path_if_unset(&self.path);
+        matcher
+    }
+
+    /// A key on several lists takes the first of passthrough, block and swallow,
+    /// so listing `CapsLock` as passthrough or block overrides the default swallow.
+    pub fn treatment(&self, code: KeyCode) -> KeyTreatment {
+        if self.passthrough.contains(&code) {
+            KeyTreatment::Passthrough
+        } else if self.block.contains(&code) {
+            KeyTreatment::Block
+        } else if self.swallow.contains(&code) {
+            KeyTreatment::Swallow
+        } else {
+            KeyTreatment::Intercept
         }
     }
 }
@@ -32,4 +109,51 @@ mod keyboard_module_test {
         assert_eq!(keyboard.name(), "My Keyboard");
         assert_eq!(keyboard.path(), "usb.0.1/input0");
     }
+
+    #[test]
+    fn device_matcher_falls_back_to_the_path() {
+        let keyboard = Keyboard::new("L1", "My Keyboard", "usb.0.1/input0");
+        assert_eq!(
+            keyboard.device_matcher().to_string(),
+            "path \"usb.0.1/input0\""
+        );
+
+        let keyboard: Keyboard = serde_json::from_str(
+            r#"{ "alias": "R1", "name": "My Keyboard", "path": "usb.0.1/input0",
+                 "match": { "name": "Kinesis*", "vendor": "29ea" } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            keyboard.device_matcher().to_string(),
+            "name \"Kinesis*\", vendor 29ea, path \"usb.0.1/input0\""
+        );
+
+        let keyboard: Keyboard = serde_json::from_str(
+            r#"{ "alias": "R1", "name": "My Keyboard", "match": { "path": "*/input0" } }"#,
+        )
+        .unwrap();
+        assert_eq!(keyboard.device_matcher().to_string(), "path \"*/input0\"");
+    }
+
+    #[test]
+    fn capslock_is_swallowed_unless_configured_otherwise() {
+        let keyboard = Keyboard::new("L1", "My Keyboard", "usb.0.1/input0");
+        let capslock = KeyCode::from("CapsLock");
+        assert_eq!(keyboard.treatment(capslock), KeyTreatment::Swallow);
+        assert_eq!(
+            keyboard.treatment(KeyCode::from("J")),
+            KeyTreatment::Intercept
+        );
+
+        let keyboard: Keyboard = serde_json::from_str(
+            r#"{ "alias": "R1", "name": "My Keyboard", "path": "usb.0.1/input0",
+                 "block": ["CapsLock"], "passthrough": ["Insert"] }"#,
+        )
+        .unwrap();
+        assert_eq!(keyboard.treatment(capslock), KeyTreatment::Block);
+        assert_eq!(
+            keyboard.treatment(KeyCode::from("Insert")),
+            KeyTreatment::Passthrough
+        );
+    }
 }
@@ -3,3 +3,4 @@ pub mod key_identifier;
 pub mod key_state;
 pub mod keyboard;
 pub mod keyboard_event;
+pub mod modifier;
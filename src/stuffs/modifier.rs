@@ -0,0 +1,165 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+use super::key_code::KeyCode;
+
+/// A key that changes what other keys do, named like a `KeyCode`.
+/// `Ctrl`, `Shift`, `Alt` and `Meta` stand for both the left and right key.
+#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(try_from = "String")]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+    Meta,
+    Key(KeyCode),
+}
+
+impl Modifier {
+    pub fn codes(self) -> Vec<KeyCode> {
+        let sides = |name: &str| {
+            vec![
+                KeyCode::from(format!("Left{name}").as_str()),
+                KeyCode::from(format!("Right{name}").as_str()),
+            ]
+        };
+
+        match self {
+            Modifier::Ctrl => sides("Ctrl"),
+            Modifier::Shift => sides("Shift"),
+            Modifier::Alt => sides("Alt"),
+            Modifier::Meta => sides("Meta"),
+            Modifier::Key(code) => vec![code],
+        }
+    }
+}
+
+impl FromStr for Modifier {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_uppercase().as_str() {
+            "CTRL" => Ok(Modifier::Ctrl),
+            "SHIFT" => Ok(Modifier::Shift),
+            "ALT" => Ok(Modifier::Alt),
+            "META" => Ok(Modifier::Meta),
+            _ => Ok(Modifier::Key(input.parse()?)),
+        }
+    }
+}
+
+impl TryFrom<String> for Modifier {
+    type Error = String;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl Display for Modifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Modifier::Ctrl => write!(f, "Ctrl"),
+            Modifier::Shift => write!(f, "Shift"),
+            Modifier::Alt => write!(f, "Alt"),
+            Modifier::Meta => write!(f, "Meta"),
+            Modifier::Key(code) => write!(f, "{code}"),
+        }
+    }
+}
+
+/// The keys a keyboard treats as modifiers: they pass through unless a rule starts with them,
+/// and are pressed again around keys replayed later.
+/// Set with `"modifiers"` on a keyboard, defaults to Ctrl, Shift, Alt, Meta and Backspace.
+#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[serde(from = "Vec<Modifier>")]
+pub struct ModifierSet {
+    codes: Vec<KeyCode>,
+}
+
+impl ModifierSet {
+    pub fn new(modifiers: &[Modifier]) -> Self {
+        let mut codes: Vec<KeyCode> = modifiers
+            .iter()
+            .copied()
+            .flat_map(Modifier::codes)
+            .collect();
+        codes.sort();
+        codes.dedup();
+        Self { codes }
+    }
+
+    pub fn contains(&self, code: KeyCode) -> bool {
+        self.codes.contains(&code)
+    }
+
+    pub fn codes(&self) -> &[KeyCode] {
+        &self.codes
+    }
+}
+
+impl Default for ModifierSet {
+    fn default() -> Self {
+        Self::new(&[
+            Modifier::Ctrl,
+            Modifier::Shift,
+            Modifier::Alt,
+            Modifier::Meta,
+            Modifier::Key(KeyCode::from("Backspace")),
+        ])
+    }
+}
+
+impl From<Vec<Modifier>> for ModifierSet {
+    fn from(modifiers: Vec<Modifier>) -> Self {
+        Self::new(&modifiers)
+    }
+}
+
+#[cfg(test)]
+mod modifier_module_test {
+    use super::*;
+
+    #[test]
+    fn can_parse_modifier_names() {
+        assert_eq!("ctrl".parse(), Ok(Modifier::Ctrl));
+        assert_eq!("Meta".parse(), Ok(Modifier::Meta));
+        assert_eq!(
+            "RightAlt".parse(),
+            Ok(Modifier::Key(KeyCode::from("RightAlt")))
+        );
+        assert_eq!(
+            "Hyper".parse::<Modifier>(),
+            Err("Invalid key name \"Hyper\"".to_string())
+        );
+    }
+
+    #[test]
+    fn groups_stand_for_both_sides() {
+        let codes: Vec<u16> = Modifier::Alt.codes().iter().map(|c| c.0).collect();
+        assert_eq!(codes, vec![56, 100]);
+        assert_eq!(Modifier::Alt.to_string(), "Alt");
+    }
+
+    #[test]
+    fn default_set_holds_ctrl_shift_alt_meta_and_backspace() {
+        let set = ModifierSet::default();
+        let codes: Vec<u16> = set.codes().iter().map(|c| c.0).collect();
+        assert_eq!(codes, vec![14, 29, 42, 54, 56, 97, 100, 125, 126]);
+        assert!(set.contains(KeyCode::from("RightAlt")));
+        assert!(set.contains(KeyCode::from("Backspace")));
+        assert!(!set.contains(KeyCode::from("CapsLock")));
+    }
+
+    #[test]
+    fn can_deserialize_a_set() {
+        let set: ModifierSet =
+            serde_json::from_str(r#"["Ctrl", "LeftShift", "LeftCtrl"]"#).unwrap();
+        let codes: Vec<u16> = set.codes().iter().map(|c| c.0).collect();
+        assert_eq!(codes, vec![29, 42, 97]);
+
+        let err = serde_json::from_str::<ModifierSet>(r#"["Hyper"]"#).unwrap_err();
+        assert!(err.to_string().contains("Invalid key name \"Hyper\""));
+    }
+}
@@ -0,0 +1,206 @@
+//! Golden-file tests: each `tests/golden/*.golden` file lists key events,
+//! and what the engine must send out and which rules it must match for them.
+//!
+//! ```text
+//! # what the case is about
+//! [events]
+//! L1 CAPSLOCK Down 0 / R1 H Down 300
+//! R1 H Up 400
+//! L1 CAPSLOCK Up 500
+//!
+//! [emitted]
+//! LEFT Down, LEFT Up
+//!
+//! [rules]
+//! L1 CAPSLOCK, R1 H
+//! ```
+//!
+//! Events are `alias key state time`, the time in milliseconds, separated by new lines or ` / `.
+//! `[emitted]` has one line per action, `[rules]` one line per rule matched.
+//! Cases use `tests/golden/config.json`, unless they have a `[config]` section of their own.
+//! Time passes as the events say, and pending rules time out a second after the last one.
+
+use std::{fs, path::Path, time::Duration};
+
+use crate::{
+    config::{self, Config},
+    interceptor::engine::Engine,
+    stuffs::{
+        key_code::KeyCode, key_identifier::KeyIdentifier, key_state::KeyState,
+        keyboard_event::KeyboardEvent,
+    },
+    test_utilities::mipoch,
+};
+
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// One `.golden` file.
+struct Case {
+    config: Option<String>,
+    events: Vec<String>,
+    emitted: Vec<String>,
+    rules: Vec<String>,
+}
+
+impl Case {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut case = Case {
+            config: None,
+            events: vec![],
+            emitted: vec![],
+            rules: vec![],
+        };
+        let mut section = None;
+
+        for line in text.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                section = Some(trimmed.to_string());
+                if trimmed == "[config]" {
+                    case.config = Some(String::new());
+                }
+                continue;
+            }
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            match section.as_deref() {
+                Some("[config]") => {
+                    let config = case.config.get_or_insert_with(String::new);
+                    config.push_str(line);
+                    config.push('\n');
+                }
+                Some("[events]") => case
+                    .events
+                    .extend(trimmed.split(" / ").map(|event| event.trim().to_string())),
+                Some("[emitted]") => case.emitted.push(trimmed.to_string()),
+                Some("[rules]") => case.rules.push(trimmed.to_string()),
+                Some(other) => return Err(format!("Unknown section {other}")),
+                None => return Err(format!("\"{trimmed}\" is outside of any section")),
+            }
+        }
+        Ok(case)
+    }
+}
+
+/// What the engine did with a case's events.
+#[derive(Debug, PartialEq, Eq)]
+struct Outcome {
+    emitted: Vec<String>,
+    rules: Vec<String>,
+}
+
+fn play(config: &Config, events: &[String]) -> Result<Outcome, String> {
+    let keyboards = config.keyboards().clone();
+    let mut engine = Engine::new(config);
+    let mut actions = vec![];
+    let mut rules = vec![];
+    let mut last = mipoch(0);
+
+    for line in events {
+        let invalid = |why: &str| format!("Invalid event \"{line}\": {why}");
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [alias, key, state, time] = fields[..] else {
+            return Err(invalid("expected \"alias key state time\""));
+        };
+        let device = keyboards
+            .iter()
+            .find(|k| k.alias() == alias)
+            .ok_or_else(|| invalid("unknown keyboard"))?;
+        let code: KeyCode = key.parse().map_err(|err: String| invalid(&err))?;
+        let state = match state.to_lowercase().as_str() {
+            "down" => KeyState::Down,
+            "up" => KeyState::Up,
+            "hold" => KeyState::Hold,
+            _ => return Err(invalid("expected Down, Up or Hold")),
+        };
+        let time = mipoch(time.parse().map_err(|_| invalid("invalid time"))?);
+
+        while let Some(deadline) = engine.deadline().filter(|d| *d <= time) {
+            actions.extend(engine.expire(deadline));
+            rules.extend(engine.take_matched());
+        }
+        let event = KeyboardEvent::new(KeyIdentifier::new(device, code), state, time);
+        actions.extend(engine.handle(event));
+        rules.extend(engine.take_matched());
+        last = time;
+    }
+
+    while let Some(deadline) = engine.deadline().filter(|d| *d <= last + SETTLE) {
+        actions.extend(engine.expire(deadline));
+        rules.extend(engine.take_matched());
+    }
+
+    Ok(Outcome {
+        emitted: actions.iter().map(ToString::to_string).collect(),
+        rules,
+    })
+}
+
+fn run_case(path: &Path, default_config: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let case = Case::parse(&text)?;
+    let config = config::parse(
+        &path.display().to_string(),
+        case.config.as_deref().unwrap_or(default_config),
+    )
+    .map_err(|err| err.to_string())?;
+
+    let expected = Outcome {
+        emitted: case.emitted,
+        rules: case.rules,
+    };
+    let actual = play(&config, &case.events)?;
+    if actual == expected {
+        return Ok(());
+    }
+    Err(format!(
+        "expected\n[emitted]\n{}\n[rules]\n{}\n\ngot\n[emitted]\n{}\n[rules]\n{}",
+        expected.emitted.join("\n"),
+        expected.rules.join("\n"),
+        actual.emitted.join("\n"),
+        actual.rules.join("\n"),
+    ))
+}
+
+#[test]
+fn golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let default_config = fs::read_to_string(dir.join("config.json")).unwrap();
+
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| matches!(path.extension(), Some(ext) if ext == "golden"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no golden files in {}", dir.display());
+
+    let failures: Vec<String> = paths
+        .iter()
+        .filter_map(|path| {
+            run_case(path, &default_config)
+                .err()
+                .map(|err| format!("{}: {err}", path.display()))
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn cases_have_sections() {
+    let case = Case::parse(
+        "# a case\n[events]\nL1 CAPSLOCK Down 0 / L1 CAPSLOCK Up 100\n\n[emitted]\nESC Down, ESC Up\n\n[rules]\n",
+    )
+    .unwrap();
+    assert_eq!(
+        case.events,
+        vec!["L1 CAPSLOCK Down 0", "L1 CAPSLOCK Up 100"]
+    );
+    assert_eq!(case.emitted, vec!["ESC Down, ESC Up"]);
+    assert!(case.rules.is_empty() && case.config.is_none());
+
+    assert!(Case::parse("L1 CAPSLOCK Down 0").is_err());
+    assert!(Case::parse("[expected]\nESC Down").is_err());
+}
@@ -5,3 +5,6 @@ use std::time::{Duration, SystemTime};
 pub fn mipoch(milis: u64) -> SystemTime {
     SystemTime::UNIX_EPOCH + Duration::from_millis(milis)
 }
+
+#[cfg(test)]
+mod golden;